@@ -0,0 +1,138 @@
+//! Time the count of a large synthetic election, with ballots held one by one and in a trie.
+//!
+//! Run with `cargo bench`. The election has 150 candidates standing for 12 vacancies, with IDs
+//! offset as they are for every state but the first, and 400,000 papers. Most papers follow one of
+//! a handful of tickets, like votes above the line, and the rest number candidates at random,
+//! favouring the popular ones, so the count has surpluses as well as exclusions. Ingesting the
+//! ballots and counting them are timed separately.
+
+extern crate aus_senate;
+extern crate rand;
+
+use aus_senate::ballot::Ballot;
+use aus_senate::candidate::*;
+use aus_senate::stats::Stats;
+use aus_senate::voting::*;
+use rand::{Rng, SeedableRng, StdRng};
+use std::time::{Duration, Instant};
+
+const NUM_CANDIDATES: CandidateId = 150;
+const FIRST_ID: CandidateId = 300;
+const NUM_PAPERS: usize = 400_000;
+const NUM_VACANCIES: usize = 12;
+const NUM_TICKETS: usize = 10;
+/// Proportion of papers following a ticket, in percent.
+const TICKET_PERCENT: u32 = 70;
+const RUNS: usize = 5;
+
+fn candidates() -> CandidateMap {
+    (FIRST_ID..FIRST_ID + NUM_CANDIDATES)
+        .map(|id| {
+            let candidate = Candidate {
+                id,
+                surname: format!("CANDIDATE{}", id),
+                other_names: String::new(),
+                group_name: "UG".to_string(),
+                party: String::new(),
+                state: "NSW".to_string(),
+            };
+            (id, candidate)
+        })
+        .collect()
+}
+
+/// Preferences for between 6 and 30 candidates, each more likely to be for a candidate with a
+/// low ID.
+fn random_prefs(rng: &mut StdRng) -> Vec<CandidateId> {
+    let n = u32::from(NUM_CANDIDATES);
+    let len = rng.gen_range(6, 31);
+    let mut prefs = Vec::with_capacity(len);
+    while prefs.len() < len {
+        let r = rng.gen_range(0, n);
+        let id = FIRST_ID + (r * r / n) as CandidateId;
+        if !prefs.contains(&id) {
+            prefs.push(id);
+        }
+    }
+    prefs
+}
+
+fn ballots() -> Vec<Ballot> {
+    let mut rng = StdRng::from_seed(&[2016][..]);
+    let tickets: Vec<_> = (0..NUM_TICKETS).map(|_| random_prefs(&mut rng)).collect();
+
+    (0..NUM_PAPERS)
+        .map(|_| {
+            let prefs = if rng.gen_range(0, 100) < TICKET_PERCENT {
+                // Popular tickets are followed more often.
+                let t = rng.gen_range(0, NUM_TICKETS);
+                tickets[t * t / NUM_TICKETS].clone()
+            } else {
+                random_prefs(&mut rng)
+            };
+            Ballot::single(prefs)
+        })
+        .collect()
+}
+
+/// Median of the times taken by a number of runs.
+fn median(run: impl Fn() -> Duration) -> Duration {
+    let mut times: Vec<_> = (0..RUNS).map(|_| run()).collect();
+    times.sort();
+    times[RUNS / 2]
+}
+
+/// Ingest the ballots, holding them the way the options ask, returning them with the median time
+/// taken to do so.
+fn time_ingest(ballots: &[Ballot], options: &CountOptions) -> (Ballots, Stats, Duration) {
+    let ingest = || {
+        let start = Instant::now();
+        let mut ingest = Ingest::new(options);
+        for ballot in ballots {
+            ingest.add(Ok(ballot.clone())).expect("ingest failed");
+        }
+        let (ballots, stats) = ingest.into_ballots(options);
+        (ballots, stats, start.elapsed())
+    };
+    let time = median(|| ingest().2);
+    let (ballots, stats, _) = ingest();
+    (ballots, stats, time)
+}
+
+/// Median time to count ballots already ingested.
+fn time_count(
+    candidates: &CandidateMap,
+    ballots: &Ballots,
+    stats: &Stats,
+    options: &CountOptions,
+) -> Duration {
+    median(|| {
+        let start = Instant::now();
+        let result =
+            recount(candidates, &[], ballots, stats, NUM_VACANCIES, options).expect("count failed");
+        assert_eq!(result.senators.len(), NUM_VACANCIES);
+        start.elapsed()
+    })
+}
+
+fn main() {
+    let candidates = candidates();
+    let ballots = ballots();
+
+    for &ballot_trie in &[false, true] {
+        let options = CountOptions {
+            ballot_trie,
+            ..CountOptions::default()
+        };
+
+        let (held, stats, ingest) = time_ingest(&ballots, &options);
+        let count = time_count(&candidates, &held, &stats, &options);
+
+        println!(
+            "{:<12} ingest: {:.3}s, count: {:.3}s",
+            if ballot_trie { "ballot trie" } else { "papers" },
+            ingest.as_secs_f64(),
+            count.as_secs_f64()
+        );
+    }
+}
@@ -28,7 +28,8 @@ def run():
 
         state_csv = os.path.join(data_dir, "{}.csv".format(state))
 
-        args = [candidate_ordering, state_csv, state, str(num_senators)]
+        args = ["--threads={}".format(os.cpu_count() or 1),
+                candidate_ordering, state_csv, state, str(num_senators)]
 
         sp.call(cargo + args)
 
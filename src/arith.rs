@@ -1,38 +1,76 @@
-use ballot::Ballot;
 use candidate::*;
-use stats::Stats;
 use util::*;
 use vote_map::*;
 
-/// Given a list of ballots, group them by next valid candidate.
-pub fn group_ballots_by_candidate<'a>(
-    vote_map: &VoteMap<'a>,
-    ballots: Vec<&'a mut Ballot>,
-    vote_round: usize,
-    transfer_val: &Frac,
-    stats: &mut Stats,
-) -> HashMap<CandidateId, Vec<&'a mut Ballot>> {
-    let mut map = HashMap::new();
+/// Given a list of papers, group them by next valid candidate, in order of candidate ID.
+pub fn group_ballots_by_candidate<P: Papers>(
+    vote_map: &VoteMap<P>,
+    ballots: Vec<P>,
+) -> Vec<(CandidateId, Vec<P>)> {
+    let store = vote_map.store();
+    let mut groups: Vec<Vec<P>> = (0..vote_map.num_candidates()).map(|_| vec![]).collect();
+    let mut next = vec![];
 
     for ballot in ballots {
-        if let Some(i) = vote_map.find_next_valid_preference(ballot) {
-            ballot.set_current(i);
+        ballot.next_preferences(store, |id| vote_map.is_continuing(id), &mut next);
 
-            let continuing_candidate = ballot.prefs[ballot.current()];
-
-            let bucket = map.entry(continuing_candidate).or_insert_with(Vec::new);
-            bucket.push(ballot);
-        } else {
-            stats.record_exhausted_vote(vote_round, transfer_val);
+        for papers in next.drain(..) {
+            groups[vote_map.index_of(papers.candidate(store))].push(papers);
         }
     }
 
-    map
+    groups
+        .into_iter()
+        .enumerate()
+        .filter(|(_, group)| !group.is_empty())
+        .map(|(idx, group)| (vote_map.id_at(idx), group))
+        .collect()
 }
 
-/// Compute the value of a list of ballots at a given weight (transfer value).
-pub fn ballot_value<'a>(weight: &Frac, ballots: &[&'a mut Ballot]) -> Int {
-    let num_ballots: u32 = ballots.iter().map(|b| b.weight()).sum();
+/// Compute the value of a list of papers at a given weight (transfer value).
+pub fn ballot_value<P: Papers>(weight: &Frac, ballots: &[P], store: &P::Store) -> Int {
+    let num_ballots: u32 = ballots.iter().map(|p| p.weight(store)).sum();
     let value = weight * frac!(num_ballots);
     value.floor()
 }
+
+/// Truncate a fraction to the given number of decimal places.
+pub fn truncate(value: &Frac, decimal_places: u32) -> Frac {
+    let scale = Int::from(10).pow(decimal_places);
+    Frac::ratio(&(value * Frac::from(&scale)).floor(), &scale)
+}
+
+/// Round a fraction up to the given number of decimal places.
+pub fn round_up(value: &Frac, decimal_places: u32) -> Frac {
+    let scale = Int::from(10).pow(decimal_places);
+    Frac::ratio(&(value * Frac::from(&scale)).ceil(), &scale)
+}
+
+/// Format a fraction in decimal, truncated to the given number of places.
+pub fn decimal(value: &Frac, places: usize) -> String {
+    let scale = Int::from(10).pow(places as u32);
+    let scaled = (value * Frac::from(&scale)).floor();
+    let whole = &scaled / &scale;
+    let fraction = &scaled % &scale;
+    format!(
+        "{}.{:0>width$}",
+        whole,
+        format!("{}", fraction),
+        width = places
+    )
+}
+
+/// Parse a number written in decimal, like `12` or `12.50`, exactly.
+pub fn parse_decimal(value: &str) -> Option<Frac> {
+    let value = value.trim();
+    let (whole, fraction) = match value.find('.') {
+        Some(point) => (&value[..point], &value[point + 1..]),
+        None => (value, ""),
+    };
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits: Int = format!("{}{}", whole, fraction).parse().ok()?;
+    let scale = Int::from(10).pow(fraction.len() as u32);
+    Some(Frac::ratio(&digits, &scale))
+}
@@ -1,13 +1,16 @@
+use std::convert::TryFrom;
+use std::iter::FromIterator;
+
 use candidate::*;
 
 /// A Ballot represents an individual's order of preferences.
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Ballot {
     /// Ordering of candidates.
     pub prefs: Vec<CandidateId>,
     /// Index of the first candidate in `prefs` who is still in the running.
     current: usize,
-    #[cfg(feature = "support2013")]
+    /// Number of identical papers this ballot stands for.
     weight: u32,
 }
 
@@ -16,12 +19,10 @@ impl Ballot {
         Ballot {
             prefs,
             current: 0,
-            #[cfg(feature = "support2013")]
             weight: 1,
         }
     }
 
-    #[cfg(feature = "support2013")]
     pub fn multi(weight: u32, prefs: Vec<CandidateId>) -> Ballot {
         Ballot {
             prefs,
@@ -30,14 +31,13 @@ impl Ballot {
         }
     }
 
-    #[cfg(feature = "support2013")]
     pub fn weight(&self) -> u32 {
         self.weight
     }
 
-    #[cfg(not(feature = "support2013"))]
-    pub fn weight(&self) -> u32 {
-        1
+    /// Merge in more papers with the same preferences.
+    pub fn add_weight(&mut self, weight: u32) {
+        self.weight += weight;
     }
 
     pub fn current(&self) -> usize {
@@ -50,3 +50,166 @@ impl Ballot {
         self.current = idx;
     }
 }
+
+/// The preferences of many ballots packed into one buffer, so that storing them takes a few large
+/// allocations rather than one per ballot.
+///
+/// Ballots are referred to by their index, in the order they were added.
+#[derive(Debug, Clone)]
+pub struct BallotArena {
+    /// Where each ballot's preferences begin in `prefs`, followed by the end of the last ballot's.
+    offsets: Vec<u32>,
+    prefs: Vec<CandidateId>,
+    weights: Vec<u32>,
+}
+
+impl Default for BallotArena {
+    fn default() -> Self {
+        BallotArena {
+            offsets: vec![0],
+            prefs: vec![],
+            weights: vec![],
+        }
+    }
+}
+
+impl BallotArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a ballot standing for `weight` papers, returning its index.
+    ///
+    /// Fails if the ballot has more preferences than a paper can be moved through, or the arena
+    /// would hold more preferences or ballots than it can index.
+    pub fn push(&mut self, prefs: &[CandidateId], weight: u32) -> Result<usize, String> {
+        u16::try_from(prefs.len())
+            .map_err(|_| format!("ballot with {} preferences is too long", prefs.len()))?;
+        let end = u32::try_from(self.prefs.len() + prefs.len())
+            .map_err(|_| "too many preferences to hold in one ballot arena".to_string())?;
+        let idx = self.weights.len();
+        u32::try_from(idx)
+            .map_err(|_| "too many ballots to hold in one ballot arena".to_string())?;
+
+        self.prefs.extend_from_slice(prefs);
+        self.offsets.push(end);
+        self.weights.push(weight);
+        Ok(idx)
+    }
+
+    /// Merge in more papers with the same preferences as a ballot.
+    pub fn add_weight(&mut self, idx: usize, weight: u32) {
+        self.weights[idx] += weight;
+    }
+
+    /// Number of ballots, each of which may stand for several papers.
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    pub fn prefs(&self, idx: usize) -> &[CandidateId] {
+        &self.prefs[self.offsets[idx] as usize..self.offsets[idx + 1] as usize]
+    }
+
+    pub fn weight(&self, idx: usize) -> u32 {
+        self.weights[idx]
+    }
+
+    /// The preferences and weight of each ballot, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[CandidateId], u32)> {
+        (0..self.len()).map(move |idx| (self.prefs(idx), self.weight(idx)))
+    }
+
+    /// A handle to each ballot, at its first preference.
+    pub fn papers(&self) -> impl Iterator<Item = Paper> {
+        (0..self.len() as u32).map(|ballot| Paper { ballot, current: 0 })
+    }
+
+    /// A copy of the ballot a handle refers to, at the same preference.
+    pub fn ballot(&self, paper: Paper) -> Ballot {
+        let mut ballot = Ballot::multi(
+            self.weight(paper.index()),
+            self.prefs(paper.index()).to_vec(),
+        );
+        ballot.set_current(paper.current());
+        ballot
+    }
+}
+
+impl<'a> FromIterator<&'a Ballot> for BallotArena {
+    fn from_iter<I: IntoIterator<Item = &'a Ballot>>(ballots: I) -> Self {
+        let mut arena = BallotArena::new();
+        for ballot in ballots {
+            unwrap!(arena.push(&ballot.prefs, ballot.weight()));
+        }
+        arena
+    }
+}
+
+/// Handle to a ballot in an arena, at one of its preferences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Paper {
+    ballot: u32,
+    current: u16,
+}
+
+impl Paper {
+    /// Index of the ballot in its arena.
+    pub fn index(&self) -> usize {
+        self.ballot as usize
+    }
+
+    /// Index of the preference the paper is at.
+    pub fn current(&self) -> usize {
+        usize::from(self.current)
+    }
+
+    /// The same ballot, moved on to another preference.
+    pub fn at(&self, current: usize) -> Paper {
+        // The arena only holds ballots with few enough preferences to index them this way.
+        Paper {
+            ballot: self.ballot,
+            current: unwrap!(u16::try_from(current)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arena_round_trip() {
+        let ballots = [
+            Ballot::multi(3, vec![2, 0, 1]),
+            Ballot::single(vec![]),
+            Ballot::multi(2, vec![1]),
+        ];
+        let mut arena: BallotArena = ballots.iter().collect();
+        arena.add_weight(2, 5);
+        assert_eq!(arena.len(), 3);
+        assert_eq!(arena.prefs(0), &[2, 0, 1]);
+        assert!(arena.prefs(1).is_empty());
+        assert_eq!(arena.weight(2), 7);
+
+        let paper = unwrap!(arena.papers().next()).at(2);
+        let ballot = arena.ballot(paper);
+        assert_eq!((ballot.current(), ballot.prefs), (2, vec![2, 0, 1]));
+    }
+
+    #[test]
+    fn arena_rejects_long_ballot() {
+        let mut arena = BallotArena::new();
+        let prefs = vec![0; 1 << 16];
+        assert_eq!(
+            arena.push(&prefs, 1),
+            Err("ballot with 65536 preferences is too long".to_string())
+        );
+        assert!(arena.is_empty());
+        assert_eq!(arena.push(&prefs[1..], 1), Ok(0));
+    }
+}
@@ -18,7 +18,7 @@ pub enum BallotParseErr {
     InputError(Box<Error>),
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum InvalidBallotErr {
     InvalidCharacter,
     InvalidMinAbove(usize),
@@ -0,0 +1,246 @@
+//! Ballots stored as a prefix tree (trie) of their preferences.
+//!
+//! Papers with the same first few preferences share a path from the root, so a candidate's papers
+//! can be held and transferred as whole branches rather than one by one. A branch is transferred
+//! by passing on each of its children for a continuing candidate, and descending through the
+//! children for candidates who have been elected or excluded, leaving the papers that end along
+//! the way to exhaust.
+//!
+//! Counting the trie takes about as long as counting the papers one by one (see
+//! `doc/HashmapVsVec.md` for the benchmark): there are fewer branches than papers to move, but
+//! passing through the nodes of candidates who aren't continuing costs about as much as skipping
+//! their preferences on each paper.
+
+use std::iter::{self, FromIterator};
+
+use ballot::{Ballot, BallotArena};
+use candidate::CandidateId;
+use countback::QuotaPapers;
+use rules::CountingRules;
+use vote_map::*;
+
+/// A prefix tree of ballots, counting the papers that pass through each node.
+///
+/// The nodes are laid out in depth-first order, so that the nodes below each one follow it
+/// directly, and moving a branch on is a scan through consecutive nodes rather than a walk from
+/// node to node.
+#[derive(Debug, Clone)]
+pub struct BallotTrie {
+    /// The root, which stands for no preferences at all, comes first.
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    candidate: CandidateId,
+    /// Number of papers through this node.
+    papers: u32,
+    /// Number of papers whose last preference is for this node's candidate.
+    ending: u32,
+    /// Index of the first node after this node and the nodes below it.
+    end: usize,
+}
+
+impl Node {
+    fn new(candidate: CandidateId) -> Self {
+        Node {
+            candidate,
+            papers: 0,
+            ending: 0,
+            end: 0,
+        }
+    }
+}
+
+impl BallotTrie {
+    /// Build the trie from the preferences and weight of each ballot.
+    ///
+    /// With the ballots in order of their preferences, each one shares a path with the one before
+    /// as far as their preferences agree, and any new nodes come after every node already in the
+    /// trie, which keeps the nodes in depth-first order.
+    fn build(mut ballots: Vec<(&[CandidateId], u32)>) -> Self {
+        ballots.sort_unstable_by_key(|&(prefs, _)| prefs);
+
+        let mut nodes = vec![Node::new(0)];
+        // Nodes along the path of the previous ballot, starting from the root.
+        let mut path = vec![0];
+        // Nodes whose subtree hasn't ended yet, with their depth.
+        let mut open: Vec<(usize, usize)> = vec![(0, 0)];
+        let mut previous: &[CandidateId] = &[];
+
+        for (prefs, weight) in ballots {
+            let shared = previous
+                .iter()
+                .zip(prefs)
+                .take_while(|(c1, c2)| c1 == c2)
+                .count();
+            path.truncate(shared + 1);
+            for &candidate in &prefs[shared..] {
+                let idx = nodes.len();
+                let depth = path.len();
+                // The subtrees of the nodes at this depth or deeper end with the new node.
+                while open.last().is_some_and(|&(_, d)| d >= depth) {
+                    let (closed, _) = unwrap!(open.pop());
+                    nodes[closed].end = idx;
+                }
+                nodes.push(Node::new(candidate));
+                open.push((idx, depth));
+                path.push(idx);
+            }
+            for &idx in &path {
+                nodes[idx].papers += weight;
+            }
+            nodes[*unwrap!(path.last())].ending += weight;
+            previous = prefs;
+        }
+
+        let len = nodes.len();
+        for (idx, _) in open {
+            nodes[idx].end = len;
+        }
+        BallotTrie { nodes }
+    }
+
+    /// Total number of papers.
+    pub fn num_papers(&self) -> u32 {
+        self.nodes[0].papers
+    }
+
+    /// Number of nodes, not counting the root.
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    /// The branches for each first preference.
+    pub fn first_preferences(&self) -> impl Iterator<Item = Branch> + '_ {
+        let end = self.nodes[0].end;
+        iter::successors(Some(1).filter(|&idx| idx < end), move |&idx| {
+            Some(self.nodes[idx].end).filter(|&next| next < end)
+        })
+        .map(|node| Branch { node })
+    }
+}
+
+impl<'a> FromIterator<&'a Ballot> for BallotTrie {
+    fn from_iter<I: IntoIterator<Item = &'a Ballot>>(ballots: I) -> Self {
+        BallotTrie::build(
+            ballots
+                .into_iter()
+                .map(|ballot| (&ballot.prefs[..], ballot.weight()))
+                .collect(),
+        )
+    }
+}
+
+impl From<&BallotArena> for BallotTrie {
+    fn from(ballots: &BallotArena) -> Self {
+        BallotTrie::build(ballots.iter().collect())
+    }
+}
+
+/// Handle to the papers of a ballot trie that pass through one of its nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Branch {
+    node: usize,
+}
+
+impl Papers for Branch {
+    type Store = BallotTrie;
+
+    fn weight(&self, trie: &BallotTrie) -> u32 {
+        trie.nodes[self.node].papers
+    }
+
+    fn candidate(&self, trie: &BallotTrie) -> CandidateId {
+        trie.nodes[self.node].candidate
+    }
+
+    fn next_preferences<F>(self, trie: &BallotTrie, is_continuing: F, next: &mut Vec<Self>) -> u32
+    where
+        F: Fn(CandidateId) -> bool,
+    {
+        let nodes = &trie.nodes;
+        let end = nodes[self.node].end;
+        let mut exhausted = nodes[self.node].ending;
+
+        // Take the first node for a continuing candidate down each path as a branch, skipping the
+        // nodes below it, and pass through the nodes of candidates who aren't continuing.
+        let mut idx = self.node + 1;
+        while idx < end {
+            let node = &nodes[idx];
+            if is_continuing(node.candidate) {
+                next.push(Branch { node: idx });
+                idx = node.end;
+            } else {
+                exhausted += node.ending;
+                idx += 1;
+            }
+        }
+
+        exhausted
+    }
+
+    fn surplus_transfers(
+        rules: &dyn CountingRules,
+        surplus: Surplus<Self>,
+        trie: &BallotTrie,
+    ) -> Result<Vec<PreferenceTransfer<Self>>, String> {
+        match rules.plan_surplus(&surplus.tally(trie)) {
+            Some(plan) => Ok(surplus.split(plan)),
+            None => Err(format!(
+                "{} transfers surpluses paper by paper, which can't be done with a ballot trie",
+                rules.name()
+            )),
+        }
+    }
+
+    fn quota_papers(
+        _held: &TransferMap<Self>,
+        _transfers: &[PreferenceTransfer<Self>],
+    ) -> Option<QuotaPapers> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn trie(ballots: &[(u32, &[CandidateId])]) -> BallotTrie {
+        ballots
+            .iter()
+            .map(|&(weight, prefs)| Ballot::multi(weight, prefs.to_vec()))
+            .collect::<Vec<_>>()
+            .iter()
+            .collect()
+    }
+
+    #[test]
+    fn shared_prefixes() {
+        let trie = trie(&[(3, &[0, 1, 2]), (2, &[0, 1]), (4, &[0, 2]), (1, &[1])]);
+        assert_eq!(trie.num_papers(), 10);
+        assert_eq!(trie.num_nodes(), 5);
+
+        let first: Vec<_> = trie
+            .first_preferences()
+            .map(|b| (b.candidate(&trie), b.weight(&trie)))
+            .collect();
+        assert_eq!(first, vec![(0, 9), (1, 1)]);
+    }
+
+    #[test]
+    fn skip_to_next_continuing() {
+        let trie = trie(&[(3, &[0, 1, 2]), (2, &[0, 1]), (4, &[0, 2, 3]), (1, &[0])]);
+        let branch = unwrap!(trie.first_preferences().next());
+
+        // With candidate 1 gone, the papers for 0 then 1 exhaust or go on to 2.
+        let mut next = vec![];
+        let exhausted = branch.next_preferences(&trie, |id| id == 2 || id == 3, &mut next);
+        assert_eq!(exhausted, 3);
+        let next: Vec<_> = next
+            .iter()
+            .map(|b| (b.candidate(&trie), b.weight(&trie)))
+            .collect();
+        assert_eq!(next, vec![(2, 3), (2, 4)]);
+    }
+}
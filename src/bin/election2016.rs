@@ -1,48 +1,357 @@
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate unwrap;
 extern crate aus_senate;
+extern crate itertools;
 
-use aus_senate::{election2016, exhausted_votes};
+use aus_senate::countback::CasualVacancy;
+use aus_senate::meek::{self, MeekOptions};
+use aus_senate::rules::{HareClark, NswRandomSample, Senate2016, WeightedInclusiveGregory};
+use aus_senate::tie_break::{TieBreakMethod, TieHistory, TieKind, TieSettlement};
+use aus_senate::voting::CountOptions;
+use aus_senate::{candidate, dop, election2016, exhausted_votes, irv};
+use itertools::Itertools;
 use std::env;
 use std::error::Error;
+use std::rc::Rc;
+
+const USAGE: &str = "Usage: ./election2016 [options] <candidates file> <prefs file> <state> \
+                     [num candidates]
+
+Options:
+    --allocate-terms        Allocate six-year terms by order of election and by s282 recount
+    --ballot-trie           Hold the ballots in a trie of their preferences, transferring
+                            papers a branch at a time (not with NSW or Hare-Clark rules)
+    --bulk-exclusion        Exclude candidates in bulk where s273(13A) allows
+    --consenting=NAME       A candidate consenting to fill the vacancy in a countback
+    --countback=NAME        Fill the named member's seat by a countback of their quota of
+                            papers among the consenting candidates
+    --disqualify=NAME       Disqualify a candidate, named as \"SURNAME, Given names\", and
+                            compare the special count to the original
+    --disqualify-file=FILE  Disqualify the candidates named in FILE, one per line
+    --explore-ties          Check every way of breaking ties that can't be settled on votes
+    --forward-tie-break     Compare earlier counts from the first, not the most recent
+    --hare-clark            Count by the Hare-Clark rules of Tasmania and the ACT, transferring
+                            surpluses from the last parcel received
+    --irv                   Fill a single vacancy by instant-runoff voting, electing the first
+                            candidate with a majority of the continuing votes
+    --meek[=TOLERANCE]      Count by Meek's method, iterating until the surplus is at most
+                            TOLERANCE votes (a fraction, default 1/1000000)
+    --monte-carlo=N         Run the count N times and report how often each candidate is
+                            elected, for counts that draw papers at random
+    --nsw-random-sample[=SEED]
+                            Transfer surpluses by drawing papers at random from the last
+                            parcel, as in the NSW Legislative Council (default seed 0)
+    --quota=FORMULA         Quota formula: droop (rounded up), statutory-droop, exact-droop,
+                            hare or imperiali (default given by the counting rules)
+    --seed=N                Seed for random tie breaking (default 0)
+    --threads=N             Parse the preferences file on N threads (default 1)
+    --tie-breakers=FILE     Break ties using the decisions listed in FILE
+    --verify=FILE           Compare the count to a distribution of preferences from the AEC
+    --wigm[=PLACES]         Transfer surpluses by the weighted inclusive Gregory method, with
+                            transfer values truncated to PLACES decimal places";
 
 fn main_with_result() -> Result<(), Box<Error>> {
     env_logger::init()?;
 
-    let args: Vec<String> = env::args().collect();
+    let (flags, args): (Vec<String>, Vec<String>) =
+        env::args().partition(|arg| arg.starts_with("--"));
 
     if args.len() != 4 && args.len() != 5 {
-        println!("Usage: ./election2016 <candidates file> <prefs file> <state> [num candidates]");
+        println!("{}", USAGE);
         Err("invalid command line arguments.".to_string())?;
     }
 
+    let mut options = CountOptions::default();
+    let mut verify_file = None;
+    let mut disqualified = vec![];
+    let mut bulk_exclusion = false;
+    let mut weighted_gregory = None;
+    let mut random_sample = None;
+    let mut hare_clark = false;
+    let mut vacating = None;
+    let mut consenting = vec![];
+    for flag in &flags {
+        let mut parts = flag.splitn(2, '=');
+        match (unwrap!(parts.next()), parts.next()) {
+            ("--allocate-terms", None) => options.allocate_terms = true,
+            ("--ballot-trie", None) => options.ballot_trie = true,
+            ("--bulk-exclusion", None) => bulk_exclusion = true,
+            ("--consenting", Some(name)) => consenting.push(name.parse()?),
+            ("--countback", Some(name)) => vacating = Some(name.parse()?),
+            ("--disqualify", Some(name)) => disqualified.push(name.parse()?),
+            ("--disqualify-file", Some(file)) => {
+                disqualified.extend(candidate::read_candidate_names(file)?)
+            }
+            ("--explore-ties", None) => options.explore_ties = true,
+            ("--forward-tie-break", None) => options.tie_history = TieHistory::Forward,
+            ("--hare-clark", None) => hare_clark = true,
+            ("--irv", None) => options.irv = true,
+            ("--meek", None) => options.meek = Some(MeekOptions::default()),
+            ("--meek", Some(tolerance)) => {
+                options.meek = Some(MeekOptions {
+                    tolerance: tolerance
+                        .parse()
+                        .map_err(|_| format!("invalid tolerance: {}", tolerance))?,
+                    ..MeekOptions::default()
+                })
+            }
+            ("--monte-carlo", Some(runs)) => options.monte_carlo_runs = Some(runs.parse()?),
+            ("--nsw-random-sample", None) => random_sample = Some(0),
+            ("--nsw-random-sample", Some(seed)) => random_sample = Some(seed.parse()?),
+            ("--quota", Some(formula)) => options.quota = Some(formula.parse()?),
+            ("--seed", Some(seed)) => options.tie_break = TieBreakMethod::Seeded(seed.parse()?),
+            ("--threads", Some(threads)) => options.parse_threads = threads.parse()?,
+            ("--tie-breakers", Some(file)) => options.tie_break = TieBreakMethod::from_file(file)?,
+            ("--verify", Some(file)) => verify_file = Some(file.to_string()),
+            ("--wigm", None) => weighted_gregory = Some(None),
+            ("--wigm", Some(places)) => weighted_gregory = Some(Some(places.parse()?)),
+            _ => Err(format!("unknown option: {}", flag))?,
+        }
+    }
+
+    let num_methods = [
+        weighted_gregory.is_some(),
+        random_sample.is_some(),
+        hare_clark,
+    ]
+    .iter()
+    .filter(|&&chosen| chosen)
+    .count();
+    if num_methods > 1 {
+        Err("only one of --wigm, --nsw-random-sample and --hare-clark can be given")?;
+    }
+    if options.irv && options.meek.is_some() {
+        Err("only one of --irv and --meek can be given")?;
+    }
+    options.rules = if hare_clark {
+        Rc::new(HareClark)
+    } else if let Some(decimal_places) = weighted_gregory {
+        Rc::new(WeightedInclusiveGregory {
+            decimal_places,
+            bulk_exclusion,
+        })
+    } else if let Some(seed) = random_sample {
+        Rc::new(NswRandomSample::new(seed))
+    } else {
+        Rc::new(Senate2016 { bulk_exclusion })
+    };
+
     let candidates_file_name = &args[1];
     let prefs_file_name = &args[2];
     let state = &args[3];
     let num_candidates = match args.get(4) {
         Some(x) => x.parse::<usize>()?,
+        None if options.irv => 1,
         None => 12,
     };
 
-    let election_result =
-        election2016::run(candidates_file_name, prefs_file_name, state, num_candidates)?;
+    let candidates = election2016::state_candidates(candidates_file_name, state)?;
+
+    if let Some(name) = vacating {
+        options.countback = Some(CasualVacancy {
+            vacating: candidate::find_candidates_with_names(&[name], &candidates)?[0],
+            consenting: candidate::find_candidates_with_names(&consenting, &candidates)?,
+        });
+    }
 
+    let election_result = election2016::run(
+        candidates_file_name,
+        prefs_file_name,
+        state,
+        num_candidates,
+        &disqualified,
+        &options,
+    )?;
+
+    if options.irv {
+        println!("Counting rules: instant-runoff voting");
+    } else if options.meek.is_some() {
+        println!("Counting rules: Meek's method");
+    } else {
+        println!("Counting rules: {}", options.rules.name());
+    }
+    println!(
+        "Quota: {} ({})",
+        election_result.quota,
+        election_result.quota_formula.name()
+    );
     println!("=== Elected ===");
-    for &(ref c, ref votes) in &election_result.senators {
+    for (c, votes) in &election_result.senators {
         println!(
             "{} {} ({}) [{} votes]",
             c.other_names, c.surname, c.party, votes
         );
     }
 
+    if election_result.last_seat_without_quota {
+        println!("The last vacancy was filled without a quota");
+    }
+
+    for tie in &election_result.ties {
+        let how = match tie.settled_by {
+            TieSettlement::EarlierCount(count) => format!("on votes at count {}", count),
+            TieSettlement::TieBreaker => "by the tie breaker".to_string(),
+        };
+        let outcome = match tie.kind {
+            TieKind::Exclusion => "excluded",
+            TieKind::OrderOfElection => "elected first",
+            TieKind::LastVacancy => "elected",
+        };
+        println!(
+            "Tie at count {} between candidates {:?}: {} {} ({})",
+            tie.count, tie.candidates, tie.chosen, outcome, how
+        );
+    }
+
+    if election_result.tie_outcomes_incomplete {
+        println!(
+            "=== Too many ways to break the ties: only the first {} were followed ===",
+            election_result.tie_outcomes.len()
+        );
+    }
     if election_result.tied {
-        println!("Tie for the last place");
+        println!("=== Ties change the outcome ===");
+    }
+    if election_result.tied || election_result.tie_outcomes_incomplete {
+        for outcome in &election_result.tie_outcomes {
+            println!("Choosing {:?} at each tie elects:", outcome.choices);
+            for c in &outcome.senators {
+                println!("  {} {} ({})", c.other_names, c.surname, c.party);
+            }
+        }
     }
 
-    exhausted_votes::write_out(
-        &election_result.stats.exhausted_votes,
-        "results/exhausted.csv",
-    )?;
+    if let Some(ref report) = election_result.meek {
+        println!("=== Meek stages ===");
+        for (i, stage) in report.stages.iter().enumerate() {
+            let names = |ids: &[_]| -> Vec<String> {
+                ids.iter()
+                    .map(|id| candidates[id].surname.clone())
+                    .collect()
+            };
+            println!(
+                "Stage {}: {} iterations ({}), elected {:?}, excluded {:?}",
+                i + 1,
+                stage.iterations.len(),
+                if stage.converged {
+                    "converged"
+                } else {
+                    "did not converge"
+                },
+                names(&stage.elected),
+                names(&stage.excluded)
+            );
+        }
+        meek::write_out(report, &candidates, "results/meek.csv")?;
+    }
+
+    if let Some(ref report) = election_result.irv {
+        println!("=== Rounds ===");
+        for &id in candidates.keys().sorted() {
+            let votes: Vec<String> = report
+                .candidate_votes(id)
+                .iter()
+                .map(|votes| votes.map_or("-".to_string(), |v| v.to_string()))
+                .collect();
+            println!(
+                "{:<30} {}",
+                candidates[&id].surname,
+                votes
+                    .iter()
+                    .map(|v| format!("{:>9}", v))
+                    .collect::<String>()
+            );
+        }
+        let exhausted: String = report
+            .rounds
+            .iter()
+            .map(|round| format!("{:>9}", round.exhausted))
+            .collect();
+        println!("{:<30} {}", "Exhausted", exhausted);
+        irv::write_out(report, &candidates, "results/irv.csv")?;
+    }
+
+    if let Some(ref frequency) = election_result.monte_carlo {
+        println!("=== Elected in {} counts ===", frequency.runs);
+        for (c, times) in &frequency.times_elected {
+            println!(
+                "{} {} ({}): {} times ({:.1}%)",
+                c.other_names,
+                c.surname,
+                c.party,
+                times,
+                100.0 * frequency.proportion(c.id)
+            );
+        }
+    }
+
+    if let Some(ref countback) = election_result.countback {
+        let vacating = &candidates[&countback.vacancy.vacating];
+        println!(
+            "=== Countback for {} {} ===",
+            vacating.other_names, vacating.surname
+        );
+        for (i, round) in countback.rounds.iter().enumerate() {
+            let votes: Vec<String> = round
+                .votes
+                .iter()
+                .map(|(id, votes)| format!("{} {}", candidates[id].surname, votes.floor()))
+                .collect();
+            print!("Round {}: {}", i + 1, votes.join(", "));
+            match round.excluded {
+                Some(id) => println!(", excluded {}", candidates[&id].surname),
+                None => println!(),
+            }
+        }
+        let c = &countback.elected;
+        println!("Elected: {} {} ({})", c.other_names, c.surname, c.party);
+    }
+
+    if let Some((gained, lost)) = election_result.special_count_changes() {
+        println!("=== Compared to the original count ===");
+        if gained.is_empty() {
+            println!("The same senators are elected");
+        }
+        for c in gained {
+            println!("Elected: {} {} ({})", c.other_names, c.surname, c.party);
+        }
+        for c in lost {
+            println!(
+                "No longer elected: {} {} ({})",
+                c.other_names, c.surname, c.party
+            );
+        }
+    }
+
+    if let Some(ref terms) = election_result.terms {
+        println!("=== Six-year terms ===");
+        for (method, senators) in &[
+            ("order of election", &terms.order_of_election),
+            ("s282 recount", &terms.recount),
+        ] {
+            println!("By {}:", method);
+            for c in senators.iter() {
+                println!("  {} {} ({})", c.other_names, c.surname, c.party);
+            }
+        }
+        if !terms.methods_agree() {
+            println!("The methods disagree on who gets a six-year term");
+        }
+    }
+
+    exhausted_votes::write_out(&election_result.transcript, "results/exhausted.csv")?;
+    dop::write_out(&election_result, &candidates, "results/dop.csv")?;
+
+    if let Some(file) = verify_file {
+        let ours = dop::rows(&election_result, &candidates);
+        let theirs = dop::read_in(&file)?;
+        match dop::first_discrepancy(&ours, &theirs) {
+            Some(discrepancy) => print!("{}", discrepancy),
+            None => println!("Every count matches {}", file),
+        }
+    }
 
     Ok(())
 }
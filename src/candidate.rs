@@ -1,4 +1,8 @@
-use std::fmt::{Debug, Error, Formatter};
+use itertools::Itertools;
+use std::fmt::{Debug, Display, Error, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::str::FromStr;
 use util::*;
 
 /// Integer representing a candidate.
@@ -53,23 +57,84 @@ pub fn get_candidate_id_list(all_candidates: &[Candidate], state: &str) -> Vec<C
 }
 
 /// Convert a list of candidate names into a list of candidate IDs.
+///
+/// Each name must match exactly one candidate: on surname, and on the start of any of their other
+/// names, ignoring case.
 pub fn find_candidates_with_names(
     candidate_names: &[CandidateName],
     candidates: &CandidateMap,
-) -> Vec<CandidateId> {
+) -> Result<Vec<CandidateId>, String> {
     candidate_names
         .iter()
-        .flat_map(|name| {
-            candidates
-                .iter()
-                .find(|&(_, cand)| {
-                    cand.surname.to_lowercase() == name.last.to_lowercase()
-                        && cand
-                            .other_names
-                            .to_lowercase()
-                            .contains(&name.first.to_lowercase())
-                })
-                .map(|(&id, _)| id)
+        .map(|name| {
+            let matches: Vec<&Candidate> = candidates
+                .values()
+                .filter(|cand| name.matches(cand))
+                .sorted_by(|c1, c2| c1.id.cmp(&c2.id));
+            match matches.len() {
+                0 => Err(format!("no candidate named {}", name)),
+                1 => Ok(matches[0].id),
+                _ => Err(format!(
+                    "{} could be any of these candidates: {:?}",
+                    name, matches
+                )),
+            }
         })
         .collect()
 }
+
+impl CandidateName {
+    fn matches(&self, candidate: &Candidate) -> bool {
+        let first = self.first.to_lowercase();
+        candidate.surname.to_lowercase() == self.last.to_lowercase()
+            && candidate
+                .other_names
+                .to_lowercase()
+                .split_whitespace()
+                .any(|name| name.starts_with(&first))
+    }
+}
+
+impl Display for CandidateName {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        write!(fmt, "{}, {}", self.last, self.first)
+    }
+}
+
+/// Parse a name written as "SURNAME, Given names".
+impl FromStr for CandidateName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CandidateName, String> {
+        let mut parts = s.splitn(2, ',');
+        match (parts.next(), parts.next()) {
+            (Some(last), Some(first)) if !last.trim().is_empty() => Ok(CandidateName {
+                first: first.trim().to_string(),
+                last: last.trim().to_string(),
+            }),
+            _ => Err(format!(
+                "invalid candidate name (expected \"SURNAME, Given names\"): {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Read a list of candidate names from a file with one name per line, written as
+/// "SURNAME, Given names". Blank lines and lines starting with `#` are ignored.
+pub fn read_candidate_names(filename: &str) -> io::Result<Vec<CandidateName>> {
+    let reader = BufReader::new(File::open(filename)?);
+    let mut names = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let name = line
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        names.push(name);
+    }
+    Ok(names)
+}
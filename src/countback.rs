@@ -0,0 +1,225 @@
+//! Hare-Clark countbacks, which fill a casual vacancy from the departing member's papers.
+//!
+//! The papers that made up the vacating member's quota when they were elected are recounted
+//! among the unsuccessful candidates who consent to stand, each at the value it had for the
+//! member. A paper goes to the first consenting candidate after the member in its order of
+//! preference. The candidate with the fewest votes is excluded, and their papers passed on, until
+//! one candidate has a majority of the votes that remain.
+
+use std::error::Error;
+
+use ballot::{Ballot, BallotArena, Paper};
+use candidate::*;
+use itertools::Itertools;
+use senate_result::Senate;
+use tie_break::{Tie, TieBreaker, TieKind, TieSettlement};
+use util::*;
+use vote_map::TransferMap;
+use voting::Ballots;
+
+/// A member's seat falling vacant, and the candidates willing to fill it.
+#[derive(Debug, Clone)]
+pub struct CasualVacancy {
+    pub vacating: CandidateId,
+    pub consenting: Vec<CandidateId>,
+}
+
+/// Votes of the continuing candidates at one round of a countback, and the outcome.
+#[derive(Debug, Clone)]
+pub struct CountbackRound {
+    /// Votes of each continuing candidate, in order of candidate ID.
+    pub votes: Vec<(CandidateId, Frac)>,
+    /// Value of the papers without a preference for any continuing candidate.
+    pub exhausted: Frac,
+    /// The candidate excluded at this round, if nobody had a majority.
+    pub excluded: Option<CandidateId>,
+}
+
+/// Record of a countback.
+#[derive(Debug, Clone)]
+pub struct Countback {
+    pub vacancy: CasualVacancy,
+    /// Total value of the vacating member's papers, which is their quota.
+    pub quota_value: Frac,
+    pub rounds: Vec<CountbackRound>,
+    pub elected: Candidate,
+    /// Ties for exclusion, with the round (from 1) as the count at which each arose.
+    pub ties: Vec<Tie>,
+}
+
+/// The papers a member held when they were elected with a quota, and those transferred on as
+/// their surplus, kept with the result of a count so that their quota can be rebuilt.
+#[derive(Debug, Clone)]
+pub struct QuotaPapers {
+    /// Papers held at each transfer value.
+    pub held: TransferMap<Paper>,
+    /// Papers transferred on as the surplus, with the transfer value of each transfer.
+    pub transferred: Vec<(Frac, Vec<Paper>)>,
+}
+
+impl QuotaPapers {
+    /// The ballots making up the quota, as they stood when the member was elected.
+    ///
+    /// Each paper is valued at its transfer value, less the value it was transferred on at as part
+    /// of the surplus, so that together they are worth the votes the member kept. A paper
+    /// transferred on at more than it was held at, as when every paper of a surplus goes at one
+    /// transfer value, keeps nothing.
+    pub fn ballots(&self, arena: &BallotArena) -> Vec<(Ballot, Frac)> {
+        let transferred: HashMap<Paper, &Frac> = self
+            .transferred
+            .iter()
+            .flat_map(|(value, papers)| papers.iter().map(move |&p| (p, value)))
+            .collect();
+        let kept = |paper: &Paper, value: &Frac| match transferred.get(paper) {
+            Some(&transfer_value) if transfer_value < value => value - transfer_value,
+            Some(_) => Frac::zero(),
+            None => value.clone(),
+        };
+        self.held
+            .iter()
+            .flat_map(|(value, papers)| papers.iter().map(move |p| (p, value)))
+            .map(|(paper, value)| (arena.ballot(*paper), kept(paper, value)))
+            .collect()
+    }
+}
+
+/// Fill a casual vacancy from a completed count of the ballots, by recounting the vacating
+/// member's quota of papers, as recorded in the result, among the consenting candidates.
+pub fn fill_vacancy(
+    candidates: &CandidateMap,
+    ballots: &Ballots,
+    result: &Senate,
+    vacancy: &CasualVacancy,
+    tie_breaker: &mut dyn TieBreaker,
+) -> Result<Countback, Box<dyn Error>> {
+    let arena = match *ballots {
+        Ballots::Papers(ref arena) => arena,
+        Ballots::Trie(_) => {
+            return Err("a countback needs the ballot papers one by one, not in a trie".into())
+        }
+    };
+    let is_elected = |id: &CandidateId| result.senators.iter().any(|(c, _)| c.id == *id);
+    let vacating = &candidates[&vacancy.vacating];
+    if !is_elected(&vacancy.vacating) {
+        return Err(format!("{:?} wasn't elected, so has no seat to vacate", vacating).into());
+    }
+    if let Some(id) = vacancy.consenting.iter().find(|id| is_elected(id)) {
+        return Err(format!(
+            "{:?} was elected, so can't fill a casual vacancy",
+            candidates[id]
+        )
+        .into());
+    }
+    let papers = result
+        .quota_papers
+        .get(&vacancy.vacating)
+        .ok_or_else(|| {
+            format!(
+                "{:?} was elected without a quota, so has no quota of papers to recount",
+                vacating
+            )
+        })?
+        .ballots(arena);
+
+    count(candidates, vacancy, papers, tie_breaker)
+}
+
+/// Fill a casual vacancy by recounting the vacating member's quota of papers, each with the value
+/// it kept for the member, among the consenting candidates.
+pub fn count(
+    candidates: &CandidateMap,
+    vacancy: &CasualVacancy,
+    quota_papers: Vec<(Ballot, Frac)>,
+    tie_breaker: &mut dyn TieBreaker,
+) -> Result<Countback, Box<dyn Error>> {
+    if vacancy.consenting.is_empty() {
+        return Err("no candidates consented to fill the vacancy".into());
+    }
+
+    let quota_value = quota_papers
+        .iter()
+        .fold(Frac::zero(), |acc, (ballot, value)| {
+            acc + value * frac!(ballot.weight())
+        });
+
+    let mut continuing: HashSet<CandidateId> = vacancy.consenting.iter().cloned().collect();
+    let mut papers: Vec<(Ballot, Frac)> = quota_papers;
+    let mut rounds = vec![];
+    let mut ties = vec![];
+
+    loop {
+        // Move each paper on to its next preference among the continuing candidates.
+        let mut votes: BTreeMap<CandidateId, Frac> =
+            continuing.iter().map(|&id| (id, Frac::zero())).collect();
+        let mut exhausted = Frac::zero();
+        for (ballot, value) in &mut papers {
+            let value = &*value * frac!(ballot.weight());
+            let start = ballot.current();
+            match ballot.prefs[start..]
+                .iter()
+                .position(|id| continuing.contains(id))
+            {
+                Some(offset) => {
+                    ballot.set_current(start + offset);
+                    *unwrap!(votes.get_mut(&ballot.prefs[start + offset])) += value;
+                }
+                None => exhausted += value,
+            }
+        }
+
+        let total = votes.values().fold(Frac::zero(), |acc, v| acc + v);
+        let (leader, most) = unwrap!(votes
+            .iter()
+            .max_by(|(c1, v1), (c2, v2)| v1.cmp(v2).then(c2.cmp(c1))));
+        let (leader, majority) = (*leader, most * frac!(2u32) > total);
+
+        let mut round = CountbackRound {
+            votes: votes.iter().map(|(&id, v)| (id, v.clone())).collect(),
+            exhausted,
+            excluded: None,
+        };
+
+        if majority || continuing.len() == 1 {
+            rounds.push(round);
+            CANDIDATE_ORDER.write(format!(
+                "Countback for {:?}: elected {:?}",
+                candidates[&vacancy.vacating], candidates[&leader]
+            ));
+            return Ok(Countback {
+                vacancy: vacancy.clone(),
+                quota_value,
+                rounds,
+                elected: candidates[&leader].clone(),
+                ties,
+            });
+        }
+
+        let fewest = unwrap!(votes.values().min());
+        let lowest: Vec<CandidateId> = votes
+            .iter()
+            .filter(|(_, v)| v == &fewest)
+            .map(|(&id, _)| id)
+            .sorted();
+        let excluded = if lowest.len() == 1 {
+            lowest[0]
+        } else {
+            let chosen = tie_breaker.choose(&lowest)?;
+            let tied: Vec<_> = lowest.iter().map(|c| &candidates[c]).collect();
+            CANDIDATE_ORDER.write(format!(
+                "Countback tie between {:?}: {:?} excluded by the tie breaker",
+                tied, candidates[&chosen]
+            ));
+            ties.push(Tie {
+                count: rounds.len() + 1,
+                kind: TieKind::Exclusion,
+                candidates: lowest,
+                chosen,
+                settled_by: TieSettlement::TieBreaker,
+            });
+            chosen
+        };
+        continuing.remove(&excluded);
+        round.excluded = Some(excluded);
+        rounds.push(round);
+    }
+}
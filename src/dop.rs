@@ -0,0 +1,361 @@
+//! Distribution of preferences in the layout of the AEC's per-state DoP download.
+
+use arith::{decimal, parse_decimal};
+use candidate::*;
+use itertools::Itertools;
+use senate_result::Senate;
+use std::collections::BTreeSet;
+use std::fmt;
+use transcript::*;
+use util::*;
+
+/// Number of decimal places for transfer values, which the AEC truncates.
+const TRANSFER_VALUE_PLACES: usize = 6;
+
+/// One row of a DoP file: a candidate (or the exhausted or gain/loss tally) at one count.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DopRow {
+    #[serde(rename = "State")]
+    pub state: String,
+    #[serde(rename = "No Of Vacancies")]
+    pub num_vacancies: usize,
+    #[serde(rename = "Total Formal Papers")]
+    pub total_formal_papers: u32,
+    #[serde(rename = "Quota")]
+    pub quota: String,
+    #[serde(rename = "Count")]
+    pub count: usize,
+    #[serde(rename = "Ballot Position")]
+    pub ballot_position: u32,
+    #[serde(rename = "Ticket")]
+    pub ticket: String,
+    #[serde(rename = "Surname")]
+    pub surname: String,
+    #[serde(rename = "Given Name")]
+    pub given_name: String,
+    #[serde(rename = "Papers")]
+    pub papers: i64,
+    #[serde(rename = "VoteTransferred")]
+    pub votes_transferred: String,
+    #[serde(rename = "ProgressiveVoteTotal")]
+    pub progressive_vote_total: String,
+    #[serde(rename = "Transfer Value")]
+    pub transfer_value: String,
+    #[serde(rename = "Status")]
+    pub status: String,
+    #[serde(rename = "Changed")]
+    pub changed: String,
+    #[serde(rename = "Order Elected")]
+    pub order_elected: usize,
+    #[serde(rename = "Comment")]
+    pub comment: String,
+}
+
+pub const EXHAUSTED: &str = "Exhausted";
+pub const GAIN_LOSS: &str = "Gain/Loss";
+
+/// Rows of the DoP file for an election result, count by count.
+///
+/// Candidates are listed in ballot paper order, followed by the exhausted and gain/loss tallies.
+pub fn rows(result: &Senate, candidates: &CandidateMap) -> Vec<DopRow> {
+    let ballot_positions = ballot_positions(candidates);
+    let state = candidates
+        .values()
+        .next()
+        .map(|c| c.state.clone())
+        .unwrap_or_default();
+    let num_vacancies = result.senators.len();
+    let total_formal_papers = result.stats.num_valid_votes();
+    let quota = if result.quota == Frac::from(&result.quota.floor()) {
+        format!("{}", result.quota.floor())
+    } else {
+        decimal(&result.quota, TRANSFER_VALUE_PLACES)
+    };
+
+    let order_elected = |id: CandidateId| {
+        result
+            .senators
+            .iter()
+            .position(|(c, _)| c.id == id)
+            .map_or(0, |pos| pos + 1)
+    };
+
+    let mut rows = vec![];
+    let mut previous: Option<&Count> = None;
+
+    for count in &result.transcript.counts {
+        let transfer_value = match count.action {
+            CountAction::FirstPreferences => decimal(&Frac::one(), TRANSFER_VALUE_PLACES),
+            CountAction::Surplus {
+                ref transfer_value, ..
+            }
+            | CountAction::Exclusion {
+                ref transfer_value, ..
+            } => decimal(transfer_value, TRANSFER_VALUE_PLACES),
+            CountAction::LastVacancy => "0".to_string(),
+        };
+        let comment = comment(count, candidates);
+
+        let row =
+            |ballot_position, ticket: &str, surname: &str, given_name: &str, tally: &Tally| {
+                DopRow {
+                    state: state.clone(),
+                    num_vacancies,
+                    total_formal_papers,
+                    quota: quota.clone(),
+                    count: count.number,
+                    ballot_position,
+                    ticket: ticket.to_string(),
+                    surname: surname.to_string(),
+                    given_name: given_name.to_string(),
+                    papers: tally.papers_change,
+                    votes_transferred: format!("{}", tally.votes_change),
+                    progressive_vote_total: format!("{}", tally.votes),
+                    transfer_value: transfer_value.clone(),
+                    status: String::new(),
+                    changed: String::new(),
+                    order_elected: 0,
+                    comment: comment.clone(),
+                }
+            };
+
+        for (idx, candidate_count) in count.candidates.iter().enumerate() {
+            let candidate = &candidates[&candidate_count.id];
+            let changed =
+                previous.is_none_or(|prev| prev.candidates[idx].status != candidate_count.status);
+            let (status, order) = match candidate_count.status {
+                CandidateStatus::Continuing => ("", 0),
+                CandidateStatus::Elected => ("Elected", order_elected(candidate.id)),
+                CandidateStatus::Excluded => ("Excluded", 0),
+            };
+            rows.push(DopRow {
+                status: status.to_string(),
+                changed: if changed && !status.is_empty() {
+                    "True".to_string()
+                } else {
+                    String::new()
+                },
+                order_elected: order,
+                ..row(
+                    ballot_positions[&candidate.id],
+                    &candidate.group_name,
+                    &candidate.surname,
+                    &candidate.other_names,
+                    &candidate_count.tally,
+                )
+            });
+        }
+
+        rows.push(row(0, "", EXHAUSTED, "", &count.exhausted));
+
+        // The AEC reports votes lost by fraction as a negative gain.
+        let loss = &count.loss_by_fraction;
+        let gain = Tally {
+            papers: 0,
+            votes: -loss.votes.clone(),
+            papers_change: 0,
+            votes_change: -loss.votes_change.clone(),
+        };
+        rows.push(row(0, "", GAIN_LOSS, "", &gain));
+
+        previous = Some(count);
+    }
+
+    rows
+}
+
+/// Write the distribution of preferences for an election result to a CSV file.
+pub fn write_out(result: &Senate, candidates: &CandidateMap, filename: &str) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_path(filename)?;
+
+    for row in rows(result, candidates) {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Read a DoP file, such as one published by the AEC.
+pub fn read_in(filename: &str) -> csv::Result<Vec<DopRow>> {
+    let mut reader = csv::Reader::from_path(filename)?;
+    reader.deserialize().collect()
+}
+
+/// The first count at which two distributions of preferences disagree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discrepancy {
+    pub count: usize,
+    /// Description of each difference, comparing our value to theirs.
+    pub differences: Vec<String>,
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "First difference at count {} (ours vs theirs):",
+            self.count
+        )?;
+        for difference in &self.differences {
+            writeln!(f, "  {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+/// Walk two distributions of preferences side by side, and find the first count where the
+/// candidates' tallies, the exhausted votes or the excluded candidates differ.
+///
+/// Candidates are matched by name, so the rows can come from different sources.
+pub fn first_discrepancy(ours: &[DopRow], theirs: &[DopRow]) -> Option<Discrepancy> {
+    let our_counts = by_count(ours);
+    let their_counts = by_count(theirs);
+    let all_counts: BTreeSet<usize> = our_counts
+        .keys()
+        .chain(their_counts.keys())
+        .cloned()
+        .collect();
+
+    for count in all_counts {
+        let differences = match (our_counts.get(&count), their_counts.get(&count)) {
+            (Some(our_rows), Some(their_rows)) => count_differences(our_rows, their_rows),
+            (Some(_), None) => vec!["count missing from theirs".to_string()],
+            (None, _) => vec!["count missing from ours".to_string()],
+        };
+        if !differences.is_empty() {
+            return Some(Discrepancy { count, differences });
+        }
+    }
+    None
+}
+
+/// Rows grouped by their count number.
+fn by_count(rows: &[DopRow]) -> BTreeMap<usize, Vec<&DopRow>> {
+    let mut counts: BTreeMap<usize, Vec<&DopRow>> = BTreeMap::new();
+    for row in rows {
+        counts.entry(row.count).or_default().push(row);
+    }
+    counts
+}
+
+/// Whether two tallies are the same number, however they are written.
+fn same_votes(ours: &str, theirs: &str) -> bool {
+    match (parse_decimal(ours), parse_decimal(theirs)) {
+        (Some(ours), Some(theirs)) => ours == theirs,
+        _ => ours == theirs,
+    }
+}
+
+fn count_differences(ours: &[&DopRow], theirs: &[&DopRow]) -> Vec<String> {
+    let name = |row: &DopRow| {
+        if row.given_name.is_empty() {
+            row.surname.clone()
+        } else {
+            format!("{}, {}", row.surname, row.given_name)
+        }
+    };
+    let tallies = |rows: &[&DopRow]| -> BTreeMap<String, String> {
+        rows.iter()
+            .filter(|row| row.surname != GAIN_LOSS)
+            .map(|row| (name(row), row.progressive_vote_total.clone()))
+            .collect()
+    };
+    let excluded = |rows: &[&DopRow]| -> Vec<String> {
+        rows.iter()
+            .filter(|row| row.status == "Excluded" && row.changed == "True")
+            .map(|row| name(row))
+            .sorted()
+    };
+
+    let mut differences = vec![];
+
+    let our_tallies = tallies(ours);
+    let their_tallies = tallies(theirs);
+    for (name, our_votes) in &our_tallies {
+        match their_tallies.get(name) {
+            Some(their_votes) if same_votes(our_votes, their_votes) => (),
+            Some(their_votes) => differences.push(format!(
+                "{}: {} votes vs {} votes",
+                name, our_votes, their_votes
+            )),
+            None => differences.push(format!("{}: missing from theirs", name)),
+        }
+    }
+    for name in their_tallies.keys() {
+        if !our_tallies.contains_key(name) {
+            differences.push(format!("{}: missing from ours", name));
+        }
+    }
+
+    let our_excluded = excluded(ours);
+    let their_excluded = excluded(theirs);
+    if our_excluded != their_excluded {
+        differences.push(format!(
+            "excluded {:?} vs excluded {:?}",
+            our_excluded, their_excluded
+        ));
+    }
+
+    differences
+}
+
+/// Position of each candidate within their group on the ballot paper, starting from 1.
+fn ballot_positions(candidates: &CandidateMap) -> HashMap<CandidateId, u32> {
+    let mut ids: Vec<_> = candidates.keys().cloned().collect();
+    ids.sort();
+
+    let mut positions = HashMap::new();
+    let mut position = 0;
+    let mut group: Option<&str> = None;
+    for id in ids {
+        let group_name = &candidates[&id].group_name;
+        if group == Some(group_name) {
+            position += 1;
+        } else {
+            position = 1;
+            group = Some(group_name);
+        }
+        positions.insert(id, position);
+    }
+    positions
+}
+
+/// Describe the candidates elected and excluded at a count, and the papers distributed.
+fn comment(count: &Count, candidates: &CandidateMap) -> String {
+    let name = |id: &CandidateId| {
+        let c = &candidates[id];
+        format!("{}, {}", c.surname, c.other_names)
+    };
+    let mut sentences = vec![];
+
+    match count.action {
+        CountAction::Surplus {
+            ref from,
+            ref transfer_value,
+        } => sentences.push(format!(
+            "Surplus of {} distributed at a transfer value of {}.",
+            name(from),
+            decimal(transfer_value, TRANSFER_VALUE_PLACES)
+        )),
+        CountAction::Exclusion {
+            ref from,
+            ref transfer_value,
+            ..
+        } => sentences.push(format!(
+            "Papers of {} distributed at a transfer value of {}.",
+            from.iter().map(name).join(", "),
+            decimal(transfer_value, TRANSFER_VALUE_PLACES)
+        )),
+        CountAction::FirstPreferences | CountAction::LastVacancy => (),
+    }
+
+    for id in &count.excluded {
+        sentences.push(format!("{} has been excluded.", name(id)));
+    }
+    for id in &count.elected {
+        sentences.push(format!("{} has been elected.", name(id)));
+    }
+
+    sentences.join(" ")
+}
@@ -4,16 +4,28 @@ use std::fs::File;
 use ballot_parse::*;
 use candidate::*;
 use group::*;
-use parse::candidates2016;
+use parse::{candidates2016, prefs2016};
 use senate_result::Senate;
 use voting::*;
 
+/// Parse the candidates standing in a state.
+pub fn state_candidates(
+    candidates_file_name: &str,
+    state: &str,
+) -> Result<CandidateMap, Box<dyn Error>> {
+    let candidates_file = File::open(candidates_file_name)?;
+    let all_candidates = candidates2016::parse(candidates_file)?;
+    Ok(get_state_candidates(&all_candidates, state))
+}
+
 /// Parse ballots and compute the election outcome (2016 edition)
 pub fn run(
     candidates_file_name: &str,
     prefs_file_name: &str,
     state: &str,
     num_candidates: usize,
+    disqualified_names: &[CandidateName],
+    options: &CountOptions,
 ) -> Result<Senate, Box<Error>> {
     let candidates_file = File::open(candidates_file_name)?;
     let all_candidates = candidates2016::parse(candidates_file)?;
@@ -26,6 +38,7 @@ pub fn run(
     let candidates = get_state_candidates(&all_candidates, state);
     let candidate_ids = get_candidate_id_list(&all_candidates, state);
     let groups = get_group_list(&all_candidates, state);
+    let disqualified = find_candidates_with_names(disqualified_names, &candidates)?;
 
     let constraints = Constraints::official();
 
@@ -37,7 +50,27 @@ pub fn run(
     let mut csv_reader = csv::ReaderBuilder::new()
         .comment(Some(b'-'))
         .from_reader(prefs_file);
+
+    if options.parse_threads > 1 {
+        let mut ingest = Ingest::new(options);
+        prefs2016::parse_parallel(
+            &mut csv_reader,
+            &groups,
+            &candidate_ids,
+            &constraints,
+            options.parse_threads,
+            |ballots, stats| ingest.add_parsed(ballots, stats),
+        )?;
+        return count_ingested(&candidates, &disqualified, ingest, num_candidates, options);
+    }
+
     let ballots_iter = parse_preferences_file!(csv_reader, &groups, &candidate_ids, &constraints);
 
-    decide_election(&candidates, &[], ballots_iter, num_candidates)
+    decide_election(
+        &candidates,
+        &disqualified,
+        ballots_iter,
+        num_candidates,
+        options,
+    )
 }
@@ -1,5 +1,9 @@
+use transcript::Transcript;
 use util::*;
 
+/// Map from vote round (one less than the count number, round 0 being the first preferences) to
+/// the number of ballots exhausted during that round (not cumulative) and their combined value
+/// (sum of transfer value).
 pub type ExhaustedVotes = BTreeMap<usize, (usize, Frac)>;
 
 #[derive(Debug, Serialize)]
@@ -10,17 +14,34 @@ pub struct ExhaustedVoteRow {
     pub value_exhausted_num: String,
     /// Denominator of the value of votes exhausted at this round.
     pub value_exhausted_den: String,
+    /// Votes exhausted at this round, rounded down as in the transcript.
+    pub votes_exhausted: String,
 }
 
-pub fn write_out(exhausted_votes: &ExhaustedVotes, filename: &str) -> csv::Result<()> {
+/// The ballots exhausted at each count of the transcript at which any exhausted, and their value.
+pub fn from_transcript(transcript: &Transcript) -> ExhaustedVotes {
+    let mut previous_value = Frac::zero();
+    let mut exhausted_votes = ExhaustedVotes::new();
+    for (round, count) in transcript.counts.iter().enumerate() {
+        let value = &count.exhausted_value - &previous_value;
+        if count.exhausted.papers_change > 0 {
+            exhausted_votes.insert(round, (count.exhausted.papers_change as usize, value));
+        }
+        previous_value = count.exhausted_value.clone();
+    }
+    exhausted_votes
+}
+
+pub fn write_out(transcript: &Transcript, filename: &str) -> csv::Result<()> {
     let mut wtr = csv::Writer::from_path(filename)?;
 
-    for (&round, &(ballots_exhausted, ref vote_value)) in exhausted_votes {
+    for (round, (ballots_exhausted, vote_value)) in from_transcript(transcript) {
         wtr.serialize(ExhaustedVoteRow {
             round,
             ballots_exhausted,
             value_exhausted_num: format!("{}", vote_value.get_num()),
             value_exhausted_den: format!("{}", vote_value.get_den()),
+            votes_exhausted: format!("{}", transcript.counts[round].exhausted.votes_change),
         })?;
     }
     wtr.flush()?;
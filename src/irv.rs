@@ -0,0 +1,186 @@
+//! Instant-runoff voting for a single vacancy.
+//!
+//! Rather than a quota, a candidate needs a majority of the votes of the papers that haven't
+//! exhausted, which is recomputed at every round. While nobody has one, the candidate with the
+//! fewest votes is excluded and their papers passed on at full value.
+
+use itertools::Itertools;
+use std::error::Error;
+
+use ballot::BallotArena;
+use candidate::*;
+use rules::QuotaFormula;
+use senate_result::Senate;
+use stats::Stats;
+use tie_break::{Tie, TieBreaker, TieKind, TieSettlement};
+use util::*;
+
+/// Votes at one round of an instant-runoff count, and the outcome.
+#[derive(Debug, Clone)]
+pub struct Round {
+    /// Votes of each continuing candidate, in order of candidate ID.
+    pub votes: Vec<(CandidateId, u32)>,
+    /// Papers without a preference for any continuing candidate.
+    pub exhausted: u32,
+    /// Votes needed for election: a majority of the continuing votes.
+    pub majority: u32,
+    pub elected: Option<CandidateId>,
+    pub excluded: Option<CandidateId>,
+}
+
+/// Record of an instant-runoff count, round by round.
+#[derive(Debug, Clone, Default)]
+pub struct IrvReport {
+    pub rounds: Vec<Round>,
+}
+
+impl IrvReport {
+    /// Votes of a candidate at each round, or `None` from the round after their exclusion.
+    pub fn candidate_votes(&self, id: CandidateId) -> Vec<Option<u32>> {
+        self.rounds
+            .iter()
+            .map(|round| {
+                round
+                    .votes
+                    .iter()
+                    .find(|&&(c, _)| c == id)
+                    .map(|&(_, votes)| votes)
+            })
+            .collect()
+    }
+}
+
+/// Fill a single vacancy from a list of formal ballots, excluding any disqualified candidates from
+/// the start.
+pub fn count(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: &BallotArena,
+    stats: Stats,
+    tie_breaker: &mut dyn TieBreaker,
+) -> Result<Senate, Box<dyn Error>> {
+    let mut result = Senate::new();
+    result.stats = stats;
+
+    let mut continuing: HashSet<CandidateId> = candidates
+        .keys()
+        .filter(|id| !disqualified_candidates.contains(id))
+        .cloned()
+        .collect();
+    if continuing.is_empty() {
+        return Err("no candidates to elect".into());
+    }
+
+    let mut report = IrvReport::default();
+
+    loop {
+        let mut votes: BTreeMap<CandidateId, u32> = continuing.iter().map(|&id| (id, 0)).collect();
+        let mut exhausted = 0;
+        // Identical preferences were merged into weighted ballots as they were ingested, so move
+        // together.
+        for (prefs, weight) in ballots.iter() {
+            match prefs.iter().find(|id| continuing.contains(id)) {
+                Some(id) => *unwrap!(votes.get_mut(id)) += weight,
+                None => exhausted += weight,
+            }
+        }
+
+        let continuing_votes: u32 = votes.values().sum();
+        let majority = continuing_votes / 2 + 1;
+        let (&leader, &most) = unwrap!(votes
+            .iter()
+            .max_by(|(c1, v1), (c2, v2)| v1.cmp(v2).then(c2.cmp(c1))));
+
+        let mut round = Round {
+            votes: votes.iter().map(|(&id, &v)| (id, v)).collect(),
+            exhausted,
+            majority,
+            elected: None,
+            excluded: None,
+        };
+
+        if most >= majority || continuing.len() == 1 {
+            CANDIDATE_ORDER.write(format!(
+                "Elected {:?} with {} of {} votes",
+                candidates[&leader], most, continuing_votes
+            ));
+            round.elected = Some(leader);
+            report.rounds.push(round);
+            result.add_senator(leader, Int::from(most), candidates);
+            result.quota = frac!(majority);
+            result.quota_formula = QuotaFormula::Majority;
+            result.last_seat_without_quota = most < majority;
+            break;
+        }
+
+        let fewest = unwrap!(votes.values().min());
+        let lowest: Vec<CandidateId> = votes
+            .iter()
+            .filter(|&(_, v)| v == fewest)
+            .map(|(&id, _)| id)
+            .sorted();
+        let excluded = if lowest.len() == 1 {
+            lowest[0]
+        } else {
+            let chosen = tie_breaker.choose(&lowest)?;
+            let tie = Tie {
+                count: report.rounds.len() + 1,
+                kind: TieKind::Exclusion,
+                candidates: lowest,
+                chosen,
+                settled_by: TieSettlement::TieBreaker,
+            };
+            result.record_tie(tie, candidates);
+            chosen
+        };
+        CANDIDATE_ORDER.write(format!("Excluded {:?}", candidates[&excluded]));
+        continuing.remove(&excluded);
+        round.excluded = Some(excluded);
+        report.rounds.push(round);
+    }
+
+    result.irv = Some(report);
+    Ok(result)
+}
+
+#[derive(Debug, Serialize)]
+struct RoundRow {
+    round: usize,
+    candidate_id: CandidateId,
+    surname: String,
+    given_name: String,
+    votes: u32,
+    exhausted: u32,
+    majority: u32,
+    status: &'static str,
+}
+
+/// Write the votes of each continuing candidate at every round to a CSV file.
+pub fn write_out(report: &IrvReport, candidates: &CandidateMap, filename: &str) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_path(filename)?;
+
+    for (idx, round) in report.rounds.iter().enumerate() {
+        for &(id, votes) in &round.votes {
+            let status = if round.elected == Some(id) {
+                "Elected"
+            } else if round.excluded == Some(id) {
+                "Excluded"
+            } else {
+                ""
+            };
+            wtr.serialize(RoundRow {
+                round: idx + 1,
+                candidate_id: id,
+                surname: candidates[&id].surname.clone(),
+                given_name: candidates[&id].other_names.clone(),
+                votes,
+                exhausted: round.exhausted,
+                majority: round.majority,
+                status,
+            })?;
+        }
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
@@ -25,13 +25,21 @@ pub mod parse;
 mod arith;
 pub mod ballot;
 pub mod ballot_parse;
+pub mod ballot_trie;
 pub mod candidate;
+pub mod countback;
+pub mod dop;
 pub mod election2016;
 pub mod exhausted_votes;
 pub mod file_logger;
 pub mod group;
+pub mod irv;
+pub mod meek;
+pub mod rules;
 pub mod senate_result;
 pub mod stats;
+pub mod tie_break;
+pub mod transcript;
 mod vote_log;
 mod vote_map;
 pub mod voting;
@@ -0,0 +1,333 @@
+//! Meek's method of counting single transferable votes.
+//!
+//! Every candidate has a keep factor: the proportion of the value of each paper reaching them that
+//! they keep, passing the rest on to the next preference. Hopeful candidates keep everything and
+//! excluded candidates nothing, while elected candidates' keep factors are adjusted iteratively
+//! until they each hold a quota. The quota shrinks as votes exhaust. All arithmetic is exact,
+//! apart from keep factors optionally being rounded up to a number of decimal places.
+
+use itertools::Itertools;
+use std::error::Error;
+
+use arith::{decimal, round_up};
+use ballot::BallotArena;
+use candidate::*;
+use rules::QuotaFormula;
+use senate_result::Senate;
+use stats::Stats;
+use tie_break::{Tie, TieBreaker, TieKind, TieSettlement};
+use util::*;
+
+/// Decimal places for keep factors and votes in the CSV output.
+const CSV_PLACES: usize = 9;
+
+/// Options for a Meek count.
+#[derive(Debug, Clone)]
+pub struct MeekOptions {
+    /// The keep factors have converged once the elected candidates' combined surplus is no more
+    /// than this many votes.
+    pub tolerance: Frac,
+    /// Decimal places to round keep factors up to, which keeps the fractions from growing
+    /// without bound. `None` keeps them exact.
+    pub keep_factor_places: Option<u32>,
+    /// Give up on convergence after this many iterations at one stage, and carry on regardless.
+    pub max_iterations: usize,
+}
+
+impl Default for MeekOptions {
+    fn default() -> MeekOptions {
+        MeekOptions {
+            tolerance: frac!(1u64, 1_000_000u64),
+            keep_factor_places: Some(9),
+            max_iterations: 1000,
+        }
+    }
+}
+
+/// Keep factors and tallies after distributing the votes once.
+#[derive(Debug, Clone)]
+pub struct Iteration {
+    pub quota: Frac,
+    /// Keep factor of each candidate used for this iteration, in order of candidate ID.
+    pub keep_factors: Vec<(CandidateId, Frac)>,
+    /// Votes of each candidate, in order of candidate ID.
+    pub votes: Vec<(CandidateId, Frac)>,
+    pub exhausted: Frac,
+    /// Combined votes of the elected candidates in excess of the quota.
+    pub surplus: Frac,
+}
+
+/// The iterations run until the keep factors converge, and the candidates then elected or
+/// excluded.
+#[derive(Debug, Clone)]
+pub struct Stage {
+    pub iterations: Vec<Iteration>,
+    /// Whether the keep factors converged, rather than the iteration limit being reached.
+    pub converged: bool,
+    pub elected: Vec<CandidateId>,
+    pub excluded: Vec<CandidateId>,
+}
+
+/// Record of a Meek count, stage by stage.
+#[derive(Debug, Clone, Default)]
+pub struct MeekReport {
+    pub stages: Vec<Stage>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Hopeful,
+    Elected,
+    Excluded,
+}
+
+/// Count a list of formal ballots by Meek's method, excluding any disqualified candidates from
+/// the start.
+pub fn count(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: &BallotArena,
+    stats: Stats,
+    num_positions: usize,
+    options: &MeekOptions,
+    tie_breaker: &mut dyn TieBreaker,
+) -> Result<Senate, Box<dyn Error>> {
+    let mut result = Senate::new();
+    let total_votes = frac!(stats.num_valid_votes());
+    result.stats = stats;
+
+    // Identical preferences were merged into weighted ballots as they were ingested, so are
+    // distributed together.
+    let papers: Vec<(&[CandidateId], Frac)> = ballots
+        .iter()
+        .map(|(prefs, weight)| (prefs, frac!(weight)))
+        .collect();
+
+    let mut status: BTreeMap<CandidateId, Status> =
+        candidates.keys().map(|&id| (id, Status::Hopeful)).collect();
+    let mut keep_factors: BTreeMap<CandidateId, Frac> =
+        candidates.keys().map(|&id| (id, Frac::one())).collect();
+    for id in disqualified_candidates {
+        status.insert(*id, Status::Excluded);
+        keep_factors.insert(*id, Frac::zero());
+    }
+
+    let mut report = MeekReport::default();
+
+    while result.num_elected() < num_positions {
+        let mut stage = Stage {
+            iterations: vec![],
+            converged: false,
+            elected: vec![],
+            excluded: vec![],
+        };
+
+        // Adjust the elected candidates' keep factors until they converge, or until a hopeful
+        // candidate reaches the quota.
+        loop {
+            let iteration =
+                distribute(&papers, &keep_factors, &status, &total_votes, num_positions);
+            let quota_reached = iteration
+                .votes
+                .iter()
+                .any(|(id, votes)| status[id] == Status::Hopeful && votes > &iteration.quota);
+            let converged = quota_reached || iteration.surplus <= options.tolerance;
+
+            for (id, votes) in &iteration.votes {
+                if status[id] == Status::Elected && !converged {
+                    let keep = &keep_factors[id] * &iteration.quota / votes;
+                    let keep = match options.keep_factor_places {
+                        Some(places) => round_up(&keep, places),
+                        None => keep,
+                    };
+                    keep_factors.insert(*id, keep);
+                }
+            }
+            stage.iterations.push(iteration);
+
+            if converged {
+                stage.converged = true;
+                break;
+            }
+            if stage.iterations.len() == options.max_iterations {
+                warn!(
+                    "Keep factors didn't converge after {} iterations",
+                    options.max_iterations
+                );
+                break;
+            }
+        }
+
+        let (quota, votes) = {
+            let last = unwrap!(stage.iterations.last());
+            (last.quota.clone(), last.votes.clone())
+        };
+        let mut hopeful: Vec<(CandidateId, Frac)> = votes
+            .into_iter()
+            .filter(|(id, _)| status[id] == Status::Hopeful)
+            .collect();
+        hopeful.sort_by(|(c1, v1), (c2, v2)| v2.cmp(v1).then(c1.cmp(c2)));
+
+        let mut newly_elected: Vec<_> = hopeful.iter().filter(|(_, v)| v > &quota).collect();
+        if newly_elected.is_empty() && hopeful.len() + result.num_elected() <= num_positions {
+            newly_elected = hopeful.iter().collect();
+        }
+        newly_elected.truncate(num_positions - result.num_elected());
+
+        if newly_elected.is_empty() {
+            let lowest = match hopeful.last() {
+                Some((_, votes)) => votes,
+                None => {
+                    return Err(format!(
+                        "no candidates left to fill the last {} of {} vacancies",
+                        num_positions - result.num_elected(),
+                        num_positions
+                    )
+                    .into())
+                }
+            };
+            let tied: Vec<CandidateId> = hopeful
+                .iter()
+                .filter(|(_, v)| v == lowest)
+                .map(|(id, _)| *id)
+                .sorted();
+            let excluded = if tied.len() == 1 {
+                tied[0]
+            } else {
+                let chosen = tie_breaker.choose(&tied)?;
+                let tie = Tie {
+                    count: report.stages.len() + 1,
+                    kind: TieKind::Exclusion,
+                    candidates: tied,
+                    chosen,
+                    settled_by: TieSettlement::TieBreaker,
+                };
+                result.record_tie(tie, candidates);
+                chosen
+            };
+            CANDIDATE_ORDER.write(format!("Excluded {:?}", candidates[&excluded]));
+            status.insert(excluded, Status::Excluded);
+            keep_factors.insert(excluded, Frac::zero());
+            stage.excluded.push(excluded);
+        }
+
+        for (id, votes) in newly_elected {
+            CANDIDATE_ORDER.write(format!(
+                "Elected {:?} with {} votes",
+                candidates[id],
+                votes.floor()
+            ));
+            status.insert(*id, Status::Elected);
+            result.add_senator(*id, votes.floor(), candidates);
+            stage.elected.push(*id);
+        }
+
+        result.quota = quota;
+        report.stages.push(stage);
+    }
+
+    // Tallies are reported rounded down, so compare them with the quota rounded down.
+    let quota = result.quota.floor();
+    result.last_seat_without_quota = result
+        .senators
+        .last()
+        .is_some_and(|(_, votes)| votes < &quota);
+    result.quota_formula = QuotaFormula::Meek;
+    result.meek = Some(report);
+
+    Ok(result)
+}
+
+/// Distribute every paper according to the keep factors, and compute the resulting quota.
+fn distribute(
+    papers: &[(&[CandidateId], Frac)],
+    keep_factors: &BTreeMap<CandidateId, Frac>,
+    status: &BTreeMap<CandidateId, Status>,
+    total_votes: &Frac,
+    num_positions: usize,
+) -> Iteration {
+    let mut votes: BTreeMap<CandidateId, Frac> =
+        keep_factors.keys().map(|&id| (id, Frac::zero())).collect();
+    let mut exhausted = Frac::zero();
+
+    for (prefs, weight) in papers {
+        let mut remaining = weight.clone();
+        for id in prefs.iter() {
+            let keep = &keep_factors[id];
+            if keep.is_zero() {
+                continue;
+            }
+            let kept = &remaining * keep;
+            remaining -= &kept;
+            *unwrap!(votes.get_mut(id)) += kept;
+            if remaining.is_zero() {
+                break;
+            }
+        }
+        exhausted += remaining;
+    }
+
+    let quota = (total_votes - &exhausted) / frac!(num_positions as u64 + 1);
+    let surplus = votes
+        .iter()
+        .filter(|&(id, v)| status[id] == Status::Elected && v > &quota)
+        .fold(Frac::zero(), |acc, (_, v)| acc + (v - &quota));
+
+    Iteration {
+        quota,
+        keep_factors: keep_factors
+            .iter()
+            .map(|(&id, k)| (id, k.clone()))
+            .collect(),
+        votes: votes.into_iter().collect(),
+        exhausted,
+        surplus,
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct IterationRow {
+    stage: usize,
+    iteration: usize,
+    candidate_id: CandidateId,
+    surname: String,
+    given_name: String,
+    keep_factor: String,
+    votes: String,
+    quota: String,
+    exhausted: String,
+    converged: bool,
+}
+
+/// Write the keep factors and votes of each candidate at every iteration to a CSV file.
+pub fn write_out(
+    report: &MeekReport,
+    candidates: &CandidateMap,
+    filename: &str,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_path(filename)?;
+
+    for (stage_idx, stage) in report.stages.iter().enumerate() {
+        for (iteration_idx, iteration) in stage.iterations.iter().enumerate() {
+            for ((id, keep_factor), (_, votes)) in
+                iteration.keep_factors.iter().zip(&iteration.votes)
+            {
+                wtr.serialize(IterationRow {
+                    stage: stage_idx + 1,
+                    iteration: iteration_idx + 1,
+                    candidate_id: *id,
+                    surname: candidates[id].surname.clone(),
+                    given_name: candidates[id].other_names.clone(),
+                    keep_factor: decimal(keep_factor, CSV_PLACES),
+                    votes: decimal(votes, CSV_PLACES),
+                    quota: decimal(&iteration.quota, CSV_PLACES),
+                    exhausted: decimal(&iteration.exhausted, CSV_PLACES),
+                    converged: stage.converged,
+                })?;
+            }
+        }
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
@@ -1,6 +1,13 @@
 use super::prelude::*;
+use ballot::Ballot;
 use csv;
 use group::Group;
+use stats::Stats;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// Number of rows parsed together on one thread.
+const CHUNK_ROWS: usize = 10_000;
 
 #[derive(Deserialize, Debug)]
 pub struct PrefRow {
@@ -44,3 +51,204 @@ macro_rules! parse_preferences_file {
             .map(|raw_row| parse_single_ballot(raw_row, $groups, $candidates, $constraints))
     }};
 }
+
+/// Parse the rows of a preferences file on `num_threads` threads, a chunk at a time.
+///
+/// The file is read on a thread of its own. Each chunk's formal ballots, and the stats of all the
+/// ballots in it, are passed to `add` on the calling thread in the order they appear in the file,
+/// stopping at the first error it returns.
+pub fn parse_parallel<R, F>(
+    reader: &mut csv::Reader<R>,
+    groups: &[Group],
+    candidates: &[CandidateId],
+    constraints: &Constraints,
+    num_threads: usize,
+    add: F,
+) -> Result<(), Box<dyn Error>>
+where
+    R: Read + Send,
+    F: FnMut(Vec<Ballot>, Stats) -> Result<(), String>,
+{
+    parse_chunks(
+        reader,
+        groups,
+        candidates,
+        constraints,
+        num_threads,
+        CHUNK_ROWS,
+        add,
+    )
+}
+
+fn parse_chunks<R, F>(
+    reader: &mut csv::Reader<R>,
+    groups: &[Group],
+    candidates: &[CandidateId],
+    constraints: &Constraints,
+    num_threads: usize,
+    chunk_rows: usize,
+    mut add: F,
+) -> Result<(), Box<dyn Error>>
+where
+    R: Read + Send,
+    F: FnMut(Vec<Ballot>, Stats) -> Result<(), String>,
+{
+    let num_threads = num_threads.max(1);
+    let (row_tx, row_rx) = mpsc::sync_channel::<(usize, Vec<PrefRow>)>(num_threads);
+    let row_rx = Arc::new(Mutex::new(row_rx));
+    let (chunk_tx, chunk_rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        let read = scope.spawn(move || -> Result<(), String> {
+            let mut rows = reader.deserialize::<PrefRow>();
+            for idx in 0.. {
+                let chunk = rows
+                    .by_ref()
+                    .take(chunk_rows)
+                    .collect::<csv::Result<Vec<_>>>()
+                    .map_err(|e| e.to_string())?;
+                // Stop once the file is done, or the workers have given up.
+                if chunk.is_empty() || row_tx.send((idx, chunk)).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        for _ in 0..num_threads {
+            let row_rx = row_rx.clone();
+            let chunk_tx = chunk_tx.clone();
+            scope.spawn(move || loop {
+                let next = unwrap!(row_rx.lock()).recv();
+                let (idx, rows) = match next {
+                    Ok(chunk) => chunk,
+                    Err(_) => break,
+                };
+                let parsed = parse_rows(rows, groups, candidates, constraints);
+                if chunk_tx.send((idx, parsed)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop((row_rx, chunk_tx));
+
+        // Chunks can be parsed out of order, so hold each one back until those before it are in.
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (idx, parsed) in chunk_rx {
+            pending.insert(idx, parsed);
+            while let Some(parsed) = pending.remove(&next) {
+                let (ballots, stats) = parsed?;
+                add(ballots, stats)?;
+                next += 1;
+            }
+        }
+        unwrap!(read.join())?;
+        Ok(())
+    })
+}
+
+/// Parse a chunk of rows, keeping the formal ballots and recording the informal ones.
+fn parse_rows(
+    rows: Vec<PrefRow>,
+    groups: &[Group],
+    candidates: &[CandidateId],
+    constraints: &Constraints,
+) -> Result<(Vec<Ballot>, Stats), String> {
+    let mut ballots = Vec::with_capacity(rows.len());
+    let mut stats = Stats::new();
+    for row in rows {
+        match parse_ballot_str(&row.preferences, groups, candidates, constraints) {
+            Ok(ballot) => {
+                stats.record_valid_vote(&ballot);
+                ballots.push(ballot);
+            }
+            Err(InvalidBallot(err)) => stats.record_invalid_vote(err),
+            Err(InputError(e)) => return Err(e.to_string()),
+        }
+    }
+    Ok((ballots, stats))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parallel_matches_sequential() {
+        let groups = vec![
+            Group {
+                name: "A".to_string(),
+                candidate_ids: vec![0, 1],
+            },
+            Group {
+                name: "B".to_string(),
+                candidate_ids: vec![2, 3],
+            },
+        ];
+        let candidates = [0, 1, 2, 3];
+        let constraints = Constraints {
+            choice: PreferBelow,
+            counts: vec![MinAbove(1), MinBelow(2)],
+        };
+
+        let mut file = "ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,\
+                        PaperNo,Preferences\n------\n"
+            .to_string();
+        let prefs = [
+            "1,2,,,,",
+            "2,1,,,,",
+            ",,1,2,3,4",
+            ",,4,3,2,1",
+            "x,,,,,",
+            ",,,,,",
+        ];
+        for i in 0..53 {
+            file += &format!("NSW,Booth,1,{},{},\"{}\"\n", i / 10, i, prefs[i * 7 % 6]);
+        }
+        let reader = || {
+            csv::ReaderBuilder::new()
+                .comment(Some(b'-'))
+                .from_reader(file.as_bytes())
+        };
+
+        let mut expected = vec![];
+        let mut expected_stats = Stats::new();
+        let mut csv_reader = reader();
+        for ballot in parse_preferences_file!(csv_reader, &groups, &candidates, &constraints) {
+            match ballot {
+                Ok(ballot) => {
+                    expected_stats.record_valid_vote(&ballot);
+                    expected.push(ballot.prefs);
+                }
+                Err(InvalidBallot(err)) => expected_stats.record_invalid_vote(err),
+                Err(InputError(e)) => panic!("{}", e),
+            }
+        }
+
+        let mut parsed = vec![];
+        let mut stats = Stats::new();
+        parse_chunks(
+            &mut reader(),
+            &groups,
+            &candidates,
+            &constraints,
+            3,
+            4,
+            |ballots, chunk_stats| {
+                parsed.extend(ballots.into_iter().map(|b| b.prefs));
+                stats.merge(chunk_stats);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(parsed, expected);
+        assert_eq!(stats.num_valid_votes(), expected_stats.num_valid_votes());
+        assert_eq!(
+            stats.num_invalid_votes(),
+            expected_stats.num_invalid_votes()
+        );
+        assert!(stats.num_invalid_votes() > 0);
+    }
+}
@@ -0,0 +1,441 @@
+//! Rules that vary between the STV counting methods used in Australia.
+
+use std::cell::RefCell;
+use std::fmt::{self, Debug};
+use std::str::FromStr;
+
+use rand::{Rng, SeedableRng, StdRng};
+
+use arith::truncate;
+use ballot::{BallotArena, Paper};
+use candidate::CandidateId;
+use util::*;
+
+pub use vote_map::{
+    LastParcel, Papers, PreferenceTransfer, Surplus, SurplusPlan, SurplusTally, TransferMap,
+};
+
+/// How candidates are excluded when no candidate has a surplus to distribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExclusionStrategy {
+    /// Exclude the candidate standing lowest in the poll.
+    Single,
+    /// Exclude several candidates at once whenever section 273(13A) permits it, and otherwise
+    /// the lowest.
+    Bulk,
+}
+
+/// The parts of a single transferable vote count that differ between methods.
+///
+/// The count itself (allocating first preferences, electing candidates who reach the quota,
+/// distributing surpluses before excluding anyone, and recording everything in the result) is
+/// common to all of them.
+pub trait CountingRules: Debug {
+    /// Short description of the method, for reporting.
+    fn name(&self) -> String;
+
+    /// Formula for the number of votes required for election.
+    fn quota(&self) -> QuotaFormula;
+
+    /// Split the papers of an elected candidate into transfers of their surplus.
+    ///
+    /// By default this carries out the plan from `plan_surplus`, which rules that look at
+    /// individual papers must override.
+    fn surplus_transfers(
+        &self,
+        surplus: Surplus<Paper>,
+        ballots: &BallotArena,
+    ) -> Vec<PreferenceTransfer<Paper>> {
+        let plan = self
+            .plan_surplus(&surplus.tally(ballots))
+            .expect("rules without a surplus plan must transfer surpluses themselves");
+        surplus.split(plan)
+    }
+
+    /// Plan the transfer of a surplus from the number of papers held at each transfer value, for
+    /// rules that transfer whole parcels. Only these rules can count papers held in bundles, as
+    /// in a ballot trie.
+    fn plan_surplus(&self, _surplus: &SurplusTally) -> Option<SurplusPlan> {
+        None
+    }
+
+    /// How to exclude candidates.
+    fn exclusion(&self) -> ExclusionStrategy;
+
+    /// Whether ballots with identical preferences can be counted as one weighted ballot, which
+    /// they can unless papers are handled individually.
+    fn merge_identical_ballots(&self) -> bool {
+        true
+    }
+
+    /// Whether the count ends by electing every continuing candidate once there are as many of
+    /// them as there are vacancies, as in section 273(18).
+    fn elect_remaining(&self) -> bool {
+        true
+    }
+
+    /// Whether the last vacancy goes to the higher of the last two continuing candidates, without
+    /// distributing any more papers, as in section 273(17).
+    fn last_two_candidates(&self) -> bool {
+        true
+    }
+}
+
+/// Formula for the quota, the number of votes a candidate needs to be elected.
+///
+/// Votes are counted in whole numbers, so a candidate reaches a fractional quota once their
+/// votes reach it rounded up, while their surplus is their votes less the exact quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaFormula {
+    /// The votes divided by one more than the number of vacancies, rounded up. This is the
+    /// statutory Droop quota except when the division is exact, when it is one less.
+    Droop,
+    /// The votes divided by one more than the number of vacancies, disregarding any remainder,
+    /// plus one, as in section 273(8).
+    StatutoryDroop,
+    /// The votes divided by one more than the number of vacancies, exactly.
+    ExactDroop,
+    /// The votes divided by the number of vacancies.
+    Hare,
+    /// The votes divided by two more than the number of vacancies. More candidates can reach it
+    /// than there are vacancies, in which case those with the most votes are elected.
+    Imperiali,
+    /// More than half of the votes still continuing, as in instant-runoff voting for a single
+    /// vacancy, where it is recomputed at every round.
+    Majority,
+    /// The exact Droop quota of the votes not yet exhausted, as in Meek's method, where it is
+    /// recomputed at every iteration.
+    Meek,
+}
+
+impl QuotaFormula {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            QuotaFormula::Droop => "Droop",
+            QuotaFormula::StatutoryDroop => "statutory Droop",
+            QuotaFormula::ExactDroop => "exact Droop",
+            QuotaFormula::Hare => "Hare",
+            QuotaFormula::Imperiali => "Imperiali",
+            QuotaFormula::Majority => "majority of continuing votes",
+            QuotaFormula::Meek => "exact Droop of the votes not exhausted",
+        }
+    }
+
+    /// The quota for a number of formal votes and vacancies.
+    pub fn value(&self, num_votes: u32, num_positions: usize) -> Frac {
+        let num_positions = num_positions as u64;
+        match *self {
+            QuotaFormula::Droop => Frac::from(&frac!(num_votes, num_positions + 1).ceil()),
+            QuotaFormula::StatutoryDroop => {
+                frac!(u64::from(num_votes) / (num_positions + 1) + 1)
+            }
+            QuotaFormula::ExactDroop => frac!(num_votes, num_positions + 1),
+            QuotaFormula::Hare => frac!(num_votes, num_positions),
+            QuotaFormula::Imperiali => frac!(num_votes, num_positions + 2),
+            QuotaFormula::Majority => frac!(num_votes / 2 + 1),
+            QuotaFormula::Meek => frac!(num_votes, num_positions + 1),
+        }
+    }
+}
+
+impl FromStr for QuotaFormula {
+    type Err = String;
+
+    /// Parse one of `droop`, `statutory-droop`, `exact-droop`, `hare` or `imperiali`.
+    ///
+    /// The majority and Meek quotas can't be chosen: they are recomputed from the votes still
+    /// continuing as the count goes on, which only instant-runoff voting and Meek's method do,
+    /// and those counts always use their own.
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "droop" => Ok(QuotaFormula::Droop),
+            "statutory-droop" => Ok(QuotaFormula::StatutoryDroop),
+            "exact-droop" => Ok(QuotaFormula::ExactDroop),
+            "hare" => Ok(QuotaFormula::Hare),
+            "imperiali" => Ok(QuotaFormula::Imperiali),
+            _ => Err(format!("unknown quota formula: {}", s)),
+        }
+    }
+}
+
+/// The count prescribed by the Commonwealth Electoral Act 1918 for the Senate, as conducted by
+/// the AEC in 2016.
+///
+/// The quota is the Droop quota rounded up, an elected candidate's surplus is transferred from
+/// all of their papers at a single transfer value (the inclusive Gregory method), and candidates
+/// are excluded one at a time unless bulk exclusion is enabled.
+#[derive(Debug, Clone, Default)]
+pub struct Senate2016 {
+    /// Exclude several candidates at once whenever section 273(13A) permits it.
+    pub bulk_exclusion: bool,
+}
+
+impl CountingRules for Senate2016 {
+    fn name(&self) -> String {
+        "Senate (2016)".to_string()
+    }
+
+    fn quota(&self) -> QuotaFormula {
+        QuotaFormula::Droop
+    }
+
+    fn plan_surplus(&self, surplus: &SurplusTally) -> Option<SurplusPlan> {
+        // Transfer all papers together (erasing existing transfer values).
+        let num_ballots: u32 = surplus.papers.values().sum();
+
+        // Aggregate transfer value that accounts for the ones we just threw out...
+        let transfer_value = &surplus.amount() / &frac!(num_ballots);
+
+        Some(vec![(
+            surplus.papers.keys().cloned().collect(),
+            transfer_value,
+        )])
+    }
+
+    fn exclusion(&self) -> ExclusionStrategy {
+        if self.bulk_exclusion {
+            ExclusionStrategy::Bulk
+        } else {
+            ExclusionStrategy::Single
+        }
+    }
+}
+
+/// The Senate count, but with surpluses transferred by the weighted inclusive Gregory method.
+///
+/// Rather than giving all of an elected candidate's papers the same transfer value, each parcel
+/// is transferred at its existing value multiplied by the proportion of the candidate's votes that
+/// are surplus, optionally truncated to a number of decimal places (as in Scottish local
+/// elections, and the reformed Western Australian and Victorian Legislative Council counts).
+/// Each parcel is transferred at a separate count, highest value first.
+#[derive(Debug, Clone, Default)]
+pub struct WeightedInclusiveGregory {
+    /// Decimal places to truncate transfer values to, or `None` to keep them exact.
+    pub decimal_places: Option<u32>,
+    /// Exclude several candidates at once whenever section 273(13A) permits it.
+    pub bulk_exclusion: bool,
+}
+
+impl CountingRules for WeightedInclusiveGregory {
+    fn name(&self) -> String {
+        match self.decimal_places {
+            Some(places) => format!(
+                "Senate, weighted inclusive Gregory ({} decimal places)",
+                places
+            ),
+            None => "Senate, weighted inclusive Gregory".to_string(),
+        }
+    }
+
+    fn quota(&self) -> QuotaFormula {
+        QuotaFormula::Droop
+    }
+
+    fn plan_surplus(&self, surplus: &SurplusTally) -> Option<SurplusPlan> {
+        let surplus_fraction = surplus.amount() / Frac::from(&surplus.votes);
+
+        let plan = surplus
+            .papers
+            .iter()
+            .rev()
+            .filter(|&(_, &num_papers)| num_papers > 0)
+            .map(|(value, _)| {
+                let transfer_value = value * &surplus_fraction;
+                let transfer_value = match self.decimal_places {
+                    Some(places) => truncate(&transfer_value, places),
+                    None => transfer_value,
+                };
+                (vec![value.clone()], transfer_value)
+            })
+            .collect();
+        Some(plan)
+    }
+
+    fn exclusion(&self) -> ExclusionStrategy {
+        if self.bulk_exclusion {
+            ExclusionStrategy::Bulk
+        } else {
+            ExclusionStrategy::Single
+        }
+    }
+}
+
+/// The count for the New South Wales Legislative Council, in which surpluses are transferred by
+/// drawing whole ballot papers at random.
+///
+/// The quota is the statutory Droop quota. An elected candidate's surplus is taken from the last
+/// parcel of papers they received (all of their papers, if elected on first preferences). The
+/// papers of the parcel that aren't exhausted are sorted by next continuing preference, and each
+/// continuing candidate gets their proportional share of the surplus, rounded down with the
+/// remainders going to the largest fractions, as papers drawn at random from their bundle. The
+/// papers drawn are transferred at full value and the rest stay with the elected candidate. If
+/// there are no more unexhausted papers than the surplus, they are all transferred.
+///
+/// The random number generator is seeded once, so counts sharing these rules draw different
+/// papers, reproducibly.
+pub struct NswRandomSample {
+    seed: usize,
+    rng: RefCell<StdRng>,
+}
+
+impl NswRandomSample {
+    pub fn new(seed: usize) -> Self {
+        NswRandomSample {
+            seed,
+            rng: RefCell::new(StdRng::from_seed(&[seed][..])),
+        }
+    }
+}
+
+impl Debug for NswRandomSample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NswRandomSample")
+            .field("seed", &self.seed)
+            .finish()
+    }
+}
+
+impl CountingRules for NswRandomSample {
+    fn name(&self) -> String {
+        format!(
+            "NSW Legislative Council, random sample (seed {})",
+            self.seed
+        )
+    }
+
+    fn quota(&self) -> QuotaFormula {
+        QuotaFormula::StatutoryDroop
+    }
+
+    fn surplus_transfers(
+        &self,
+        mut surplus: Surplus<Paper>,
+        ballots: &BallotArena,
+    ) -> Vec<PreferenceTransfer<Paper>> {
+        // Only whole papers can be drawn.
+        let amount = surplus.amount().floor();
+        let num_papers =
+            |papers: &[Paper]| -> u64 { papers.iter().map(|p| u64::from(p.weight(ballots))).sum() };
+
+        // Sort the last parcel by next continuing preference, leaving exhausted papers behind.
+        let mut bundles: BTreeMap<CandidateId, Vec<Paper>> = BTreeMap::new();
+        for paper in surplus.take_last_parcel() {
+            if let Some(next) = surplus.next_preference(paper, ballots) {
+                bundles.entry(next).or_default().push(paper);
+            }
+        }
+        let unexhausted: u64 = bundles.values().map(|bundle| num_papers(bundle)).sum();
+
+        if Int::from(unexhausted) <= amount {
+            let all_papers = bundles.into_values().flatten().collect();
+            return vec![PreferenceTransfer(
+                surplus.candidate,
+                Frac::one(),
+                all_papers,
+            )];
+        }
+
+        // Each continuing candidate's share of the surplus, with the papers left over after
+        // rounding down going to the largest remainders.
+        let surplus_papers = unwrap!(Option::<u64>::from(&amount));
+        let mut shares: Vec<(CandidateId, u64, u64)> = bundles
+            .iter()
+            .map(|(&id, bundle)| {
+                let scaled = num_papers(bundle) * surplus_papers;
+                (id, scaled / unexhausted, scaled % unexhausted)
+            })
+            .collect();
+        let allocated: u64 = shares.iter().map(|&(_, share, _)| share).sum();
+        shares.sort_by(|(c1, _, r1), (c2, _, r2)| r2.cmp(r1).then(c1.cmp(c2)));
+        for share in shares
+            .iter_mut()
+            .take((surplus_papers - allocated) as usize)
+        {
+            share.1 += 1;
+        }
+
+        let mut rng = self.rng.borrow_mut();
+        let mut drawn = vec![];
+        for (id, share, _) in shares {
+            let mut bundle = unwrap!(bundles.remove(&id));
+            rng.shuffle(&mut bundle);
+            let mut taken = 0;
+            for paper in bundle {
+                let weight = u64::from(paper.weight(ballots));
+                if taken + weight > share {
+                    continue;
+                }
+                taken += weight;
+                drawn.push(paper);
+            }
+        }
+
+        vec![PreferenceTransfer(surplus.candidate, Frac::one(), drawn)]
+    }
+
+    fn exclusion(&self) -> ExclusionStrategy {
+        ExclusionStrategy::Single
+    }
+
+    /// Papers are drawn one by one.
+    fn merge_identical_ballots(&self) -> bool {
+        false
+    }
+}
+
+/// The Hare-Clark count used for the Tasmanian House of Assembly and the ACT Legislative Assembly.
+///
+/// The quota is the statutory Droop quota. An elected candidate's surplus is transferred from the
+/// last parcel of papers they received alone, at a transfer value of the surplus divided by the
+/// number of those papers that aren't exhausted, or the value at which the parcel was received if
+/// that is lower. Candidates are excluded one at a time, their papers being distributed a transfer
+/// value at a time, highest first. The count ends when the continuing candidates are no more than
+/// the remaining vacancies; there is no separate rule for the last two candidates, so an excluded
+/// candidate's papers are always distributed.
+#[derive(Debug, Clone, Default)]
+pub struct HareClark;
+
+impl CountingRules for HareClark {
+    fn name(&self) -> String {
+        "Hare-Clark".to_string()
+    }
+
+    fn quota(&self) -> QuotaFormula {
+        QuotaFormula::StatutoryDroop
+    }
+
+    fn surplus_transfers(
+        &self,
+        mut surplus: Surplus<Paper>,
+        ballots: &BallotArena,
+    ) -> Vec<PreferenceTransfer<Paper>> {
+        let amount = surplus.amount();
+        let received_value = surplus.last_parcel.transfer_value.clone();
+
+        // Exhausted papers are set aside with the rest of the candidate's papers.
+        let parcel: Vec<_> = surplus
+            .take_last_parcel()
+            .into_iter()
+            .filter(|&paper| surplus.next_preference(paper, ballots).is_some())
+            .collect();
+        let num_papers: u32 = parcel.iter().map(|p| p.weight(ballots)).sum();
+        if num_papers == 0 {
+            return vec![];
+        }
+
+        let transfer_value = (&amount / &frac!(num_papers)).min(received_value);
+        vec![PreferenceTransfer(
+            surplus.candidate,
+            transfer_value,
+            parcel,
+        )]
+    }
+
+    fn exclusion(&self) -> ExclusionStrategy {
+        ExclusionStrategy::Single
+    }
+
+    fn last_two_candidates(&self) -> bool {
+        false
+    }
+}
@@ -1,20 +1,136 @@
 use candidate::*;
+use countback::{Countback, QuotaPapers};
+use irv::IrvReport;
+use itertools::Itertools;
+use meek::MeekReport;
+use rules::QuotaFormula;
 use stats::Stats;
-use util::Int;
+use tie_break::{Tie, TieKind, TieSettlement};
+use transcript::Transcript;
+use util::{BTreeMap, Frac, Int, CANDIDATE_ORDER};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone)]
 pub struct Senate {
     /// List of senators and the vote tally they were elected on.
     pub senators: Vec<(Candidate, Int)>,
+    /// Number of votes required for election.
+    pub quota: Frac,
+    /// Formula the quota was computed by.
+    pub quota_formula: QuotaFormula,
+    /// Whether the last senator was elected without reaching the quota.
+    pub last_seat_without_quota: bool,
+    /// Whether different ways of breaking ties would elect different senators.
     pub tied: bool,
+    /// Outcome of every way of breaking ties, if they didn't all agree.
+    pub tie_outcomes: Vec<TieOutcome>,
+    /// Whether there were too many ways of breaking ties to follow them all, in which case
+    /// `tie_outcomes` lists only those that were followed.
+    pub tie_outcomes_incomplete: bool,
+    /// Every tie between candidates, in the order they arose.
+    pub ties: Vec<Tie>,
+    /// Distribution of preferences, count by count.
+    pub transcript: Transcript,
+    /// The papers making up the quota of each senator elected with one, from which a casual
+    /// vacancy can be filled by a countback. Empty if the ballots were counted in a trie.
+    pub quota_papers: BTreeMap<CandidateId, QuotaPapers>,
+    /// Senators elected by the original count, if this is a special count with candidates
+    /// disqualified.
+    pub original_senators: Option<Vec<Candidate>>,
+    /// Senators given six-year terms after a double dissolution, if requested.
+    pub terms: Option<TermAllocation>,
+    /// Keep factors and tallies at each iteration, for a count by Meek's method.
+    pub meek: Option<MeekReport>,
+    /// Votes at each round, for an instant-runoff count.
+    pub irv: Option<IrvReport>,
+    /// How often each candidate was elected over repeated counts, if requested.
+    pub monte_carlo: Option<ElectionFrequency>,
+    /// The countback filling a casual vacancy, if requested.
+    pub countback: Option<Countback>,
     pub stats: Stats,
 }
 
+/// Senators elected for one way of breaking the ties in a count.
+#[derive(Debug, Clone)]
+pub struct TieOutcome {
+    /// Candidate chosen at each tie that couldn't be broken on votes, in the order they arose.
+    pub choices: Vec<CandidateId>,
+    /// Senators in order of election.
+    pub senators: Vec<Candidate>,
+}
+
+impl TieOutcome {
+    /// IDs of the elected senators, sorted so that outcomes can be compared irrespective of
+    /// the order of election.
+    pub fn senator_ids(&self) -> Vec<CandidateId> {
+        let mut ids: Vec<_> = self.senators.iter().map(|c| c.id).collect();
+        ids.sort();
+        ids
+    }
+}
+
+/// Senators given six-year terms under each method the Senate may choose, the rest getting
+/// three-year terms.
+#[derive(Debug, Clone)]
+pub struct TermAllocation {
+    /// The first half of the senators, in order of election.
+    pub order_of_election: Vec<Candidate>,
+    /// The senators elected by recounting the ballots for half the vacancies (s282), in order of
+    /// election.
+    pub recount: Vec<Candidate>,
+}
+
+impl TermAllocation {
+    /// Whether both methods give six-year terms to the same senators.
+    pub fn methods_agree(&self) -> bool {
+        let ids = |senators: &[Candidate]| senators.iter().map(|c| c.id).sorted();
+        ids(&self.order_of_election) == ids(&self.recount)
+    }
+}
+
+/// The number of times each candidate was elected over repeated counts, for rules that draw
+/// papers at random.
+#[derive(Debug, Clone)]
+pub struct ElectionFrequency {
+    pub runs: usize,
+    /// Every candidate with the number of counts electing them, most often elected first.
+    pub times_elected: Vec<(Candidate, usize)>,
+}
+
+impl ElectionFrequency {
+    /// Proportion of the counts electing a candidate.
+    pub fn proportion(&self, id: CandidateId) -> f64 {
+        self.times_elected
+            .iter()
+            .find(|(c, _)| c.id == id)
+            .map_or(0.0, |&(_, times)| times as f64 / self.runs as f64)
+    }
+}
+
+impl Default for Senate {
+    fn default() -> Senate {
+        Senate::new()
+    }
+}
+
 impl Senate {
     pub fn new() -> Senate {
         Senate {
             senators: vec![],
+            quota: Frac::zero(),
+            quota_formula: QuotaFormula::StatutoryDroop,
+            last_seat_without_quota: false,
             tied: false,
+            tie_outcomes: vec![],
+            tie_outcomes_incomplete: false,
+            ties: vec![],
+            transcript: Transcript::default(),
+            quota_papers: BTreeMap::new(),
+            original_senators: None,
+            terms: None,
+            meek: None,
+            irv: None,
+            monte_carlo: None,
+            countback: None,
             stats: Stats::new(),
         }
     }
@@ -23,7 +139,44 @@ impl Senate {
         self.senators.push((candidates[&id].clone(), tally))
     }
 
+    /// Add a tie to the list of every tie in the count, and log how it was settled.
+    pub fn record_tie(&mut self, tie: Tie, candidates: &CandidateMap) {
+        let tied: Vec<_> = tie.candidates.iter().map(|c| &candidates[c]).collect();
+        let how = match tie.settled_by {
+            TieSettlement::EarlierCount(c) => format!("on votes at count {}", c),
+            TieSettlement::TieBreaker => "by the tie breaker".to_string(),
+        };
+        let outcome = match tie.kind {
+            TieKind::Exclusion => "excluded",
+            TieKind::OrderOfElection => "elected first",
+            TieKind::LastVacancy => "elected",
+        };
+        CANDIDATE_ORDER.write(format!(
+            "Tie between {:?}: {:?} {} {}",
+            tied, candidates[&tie.chosen], outcome, how
+        ));
+        self.ties.push(tie);
+    }
+
     pub fn num_elected(&self) -> usize {
         self.senators.len()
     }
+
+    /// Senators elected by the special count who weren't elected originally, and senators elected
+    /// originally who weren't elected by the special count.
+    pub fn special_count_changes(&self) -> Option<(Vec<&Candidate>, Vec<&Candidate>)> {
+        self.original_senators.as_ref().map(|original| {
+            let gained = self
+                .senators
+                .iter()
+                .map(|(c, _)| c)
+                .filter(|c| original.iter().all(|o| o.id != c.id))
+                .collect();
+            let lost = original
+                .iter()
+                .filter(|o| self.senators.iter().all(|(c, _)| c.id != o.id))
+                .collect();
+            (gained, lost)
+        })
+    }
 }
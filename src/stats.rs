@@ -1,17 +1,13 @@
 use ballot::*;
 use ballot_parse::*;
-use exhausted_votes::*;
 use util::*;
 
 pub type BallotErrorMap = HashMap<InvalidBallotErr, u32>;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Stats {
     num_valid_votes: u32,
     invalid_votes: BallotErrorMap,
-    /// Map from vote round to number of ballots exhausted during that round (not cumulative) and
-    /// their combined value (sum of transfer value).
-    pub exhausted_votes: ExhaustedVotes,
 }
 
 impl Stats {
@@ -28,13 +24,12 @@ impl Stats {
         *err_count += 1;
     }
 
-    pub fn record_exhausted_vote(&mut self, round: usize, transfer_value: &Frac) {
-        let &mut (ref mut count, ref mut value) = self
-            .exhausted_votes
-            .entry(round)
-            .or_insert_with(|| (0, frac!(0u64)));
-        *count += 1;
-        *value += transfer_value;
+    /// Add in the votes recorded separately, say while parsing another part of the same file.
+    pub fn merge(&mut self, other: Stats) {
+        self.num_valid_votes += other.num_valid_votes;
+        for (err, count) in other.invalid_votes {
+            *self.invalid_votes.entry(err).or_insert(0) += count;
+        }
     }
 
     pub fn num_total_votes(&self) -> u32 {
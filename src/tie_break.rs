@@ -0,0 +1,276 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use candidate::CandidateId;
+
+use rand::{Rng, SeedableRng, StdRng};
+
+/// Method for choosing between candidates whose tie can't be broken by their vote tallies.
+pub trait TieBreaker {
+    /// Choose one of the tied candidates, which are given in order of candidate ID.
+    fn choose(&mut self, tied: &[CandidateId]) -> Result<CandidateId, String>;
+}
+
+/// How ties that can't be broken on votes are resolved, for counts that don't explore them all.
+#[derive(Debug, Clone)]
+pub enum TieBreakMethod {
+    /// Draw a candidate at random, from a random number generator with the given seed.
+    Seeded(usize),
+    /// Follow a list of Electoral Officer decisions, each an index into the list of tied
+    /// candidates sorted by ID.
+    Scripted(Vec<usize>),
+}
+
+impl Default for TieBreakMethod {
+    fn default() -> Self {
+        TieBreakMethod::Seeded(0)
+    }
+}
+
+impl TieBreakMethod {
+    /// Read a list of decisions from a file with one index per line, such as
+    /// `misc/NSW_tie_breakers.txt`. Blank lines and lines starting with `#` are ignored.
+    pub fn from_file(filename: &str) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(filename)?);
+        let mut decisions = vec![];
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let decision = line.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid tie breaker decision: {}", line),
+                )
+            })?;
+            decisions.push(decision);
+        }
+        Ok(TieBreakMethod::Scripted(decisions))
+    }
+
+    pub fn tie_breaker(&self) -> Box<dyn TieBreaker> {
+        self.tie_breaker_for_run(0)
+    }
+
+    /// The tie breaker for the given run of a repeated count, numbered from 0 for the first. Each
+    /// run draws from its own seed, the base seed plus the run number, so that runs don't all
+    /// break their ties the same way. Scripted decisions are the same for every run.
+    pub fn tie_breaker_for_run(&self, run: usize) -> Box<dyn TieBreaker> {
+        match *self {
+            TieBreakMethod::Seeded(seed) => Box::new(SeededTieBreaker::new(seed.wrapping_add(run))),
+            TieBreakMethod::Scripted(ref decisions) => {
+                Box::new(ScriptedTieBreaker::new(decisions.clone()))
+            }
+        }
+    }
+}
+
+/// Choose a tied candidate at random, reproducibly.
+pub struct SeededTieBreaker {
+    rng: StdRng,
+}
+
+impl SeededTieBreaker {
+    pub fn new(seed: usize) -> Self {
+        SeededTieBreaker {
+            rng: StdRng::from_seed(&[seed][..]),
+        }
+    }
+}
+
+impl TieBreaker for SeededTieBreaker {
+    fn choose(&mut self, tied: &[CandidateId]) -> Result<CandidateId, String> {
+        Ok(*unwrap!(self.rng.choose(tied)))
+    }
+}
+
+/// Choose tied candidates according to a list of decisions made ahead of time.
+pub struct ScriptedTieBreaker {
+    decisions: VecDeque<usize>,
+}
+
+impl ScriptedTieBreaker {
+    pub fn new(decisions: Vec<usize>) -> Self {
+        ScriptedTieBreaker {
+            decisions: decisions.into(),
+        }
+    }
+}
+
+impl TieBreaker for ScriptedTieBreaker {
+    fn choose(&mut self, tied: &[CandidateId]) -> Result<CandidateId, String> {
+        let decision = self
+            .decisions
+            .pop_front()
+            .ok_or_else(|| format!("no tie breaker decision for tie between {:?}", tied))?;
+        tied.get(decision).cloned().ok_or_else(|| {
+            format!(
+                "tie breaker decision {} out of range for tie between {:?}",
+                decision, tied
+            )
+        })
+    }
+}
+
+/// How to use the candidates' votes at earlier counts to break a tie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieHistory {
+    /// Look back to the most recent count at which the tied candidates all had unequal votes,
+    /// as required by section 273(22).
+    #[default]
+    Backward,
+    /// Compare votes count by count starting from the first.
+    Forward,
+}
+
+/// How a tie was settled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TieSettlement {
+    /// By the candidates' votes at the given earlier count.
+    EarlierCount(usize),
+    /// By the tie breaker (a random draw or an Electoral Officer decision).
+    TieBreaker,
+}
+
+/// What a tie between candidates decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TieKind {
+    /// Which of the candidates with the fewest votes to exclude.
+    Exclusion,
+    /// Which of the candidates reaching the quota with equal votes is elected first (and so has
+    /// their surplus transferred first).
+    OrderOfElection,
+    /// Which of the last two continuing candidates fills the last vacancy.
+    LastVacancy,
+}
+
+/// Record of a tie between candidates and how it was settled.
+#[derive(Debug, Clone)]
+pub struct Tie {
+    /// Count at which the tie arose.
+    pub count: usize,
+    pub kind: TieKind,
+    /// Tied candidates, in order of candidate ID.
+    pub candidates: Vec<CandidateId>,
+    /// Candidate excluded, or elected (first).
+    pub chosen: CandidateId,
+    pub settled_by: TieSettlement,
+}
+
+/// Tie breaker used to walk every branch of a count, one branch per run.
+///
+/// Ties are resolved according to a path of choices (indices into the list of tied candidates),
+/// and any tie beyond the end of the path is resolved by taking the first candidate.
+pub struct ForkTieBreaker {
+    /// Index of the candidate chosen at each tie.
+    path: Vec<usize>,
+    /// Number of candidates involved in each tie encountered so far.
+    num_tied: Vec<usize>,
+    /// Candidate chosen at each tie encountered so far.
+    choices: Vec<CandidateId>,
+}
+
+impl ForkTieBreaker {
+    pub fn new(path: Vec<usize>) -> Self {
+        ForkTieBreaker {
+            path,
+            num_tied: vec![],
+            choices: vec![],
+        }
+    }
+
+    /// Candidates chosen at each tie during this run.
+    pub fn choices(&self) -> &[CandidateId] {
+        &self.choices
+    }
+
+    /// Path to the next unexplored branch, in depth-first order.
+    pub fn next_path(&self) -> Option<Vec<usize>> {
+        let depth = self.num_tied.len();
+        (0..depth)
+            .rev()
+            .find(|&i| self.path[i] + 1 < self.num_tied[i])
+            .map(|i| {
+                let mut next = self.path[..i].to_vec();
+                next.push(self.path[i] + 1);
+                next
+            })
+    }
+}
+
+impl TieBreaker for ForkTieBreaker {
+    fn choose(&mut self, tied: &[CandidateId]) -> Result<CandidateId, String> {
+        let tie_num = self.num_tied.len();
+        if tie_num == self.path.len() {
+            self.path.push(0);
+        }
+        let choice = tied[self.path[tie_num].min(tied.len() - 1)];
+        self.num_tied.push(tied.len());
+        self.choices.push(choice);
+        Ok(choice)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fork_paths() {
+        // First run: two ties, with 2 and 3 candidates.
+        let mut t = ForkTieBreaker::new(vec![]);
+        assert_eq!(t.choose(&[4, 5]), Ok(4));
+        assert_eq!(t.choose(&[1, 2, 3]), Ok(1));
+        assert_eq!(t.next_path(), Some(vec![0, 1]));
+
+        let mut t = ForkTieBreaker::new(vec![0, 2]);
+        t.choose(&[4, 5]).unwrap();
+        assert_eq!(t.choose(&[1, 2, 3]), Ok(3));
+        assert_eq!(t.next_path(), Some(vec![1]));
+
+        // Choosing the other candidate at the first tie avoids the second tie.
+        let mut t = ForkTieBreaker::new(vec![1]);
+        assert_eq!(t.choose(&[4, 5]), Ok(5));
+        assert_eq!(t.choices(), &[5]);
+        assert_eq!(t.next_path(), None);
+    }
+
+    #[test]
+    fn seeded_is_reproducible() {
+        let tied: Vec<CandidateId> = (0..100).collect();
+        let mut t1 = SeededTieBreaker::new(42);
+        let mut t2 = SeededTieBreaker::new(42);
+        for _ in 0..10 {
+            assert_eq!(t1.choose(&tied), t2.choose(&tied));
+        }
+    }
+
+    #[test]
+    fn runs_numbered_from_zero() {
+        let tied: Vec<CandidateId> = (0..100).collect();
+        let method = TieBreakMethod::Seeded(42);
+        let draws = |mut t: Box<dyn TieBreaker>| -> Vec<_> {
+            (0..10).map(|_| unwrap!(t.choose(&tied))).collect()
+        };
+        assert_eq!(
+            draws(method.tie_breaker_for_run(0)),
+            draws(method.tie_breaker())
+        );
+        assert_eq!(
+            draws(method.tie_breaker_for_run(1)),
+            draws(Box::new(SeededTieBreaker::new(43)))
+        );
+    }
+
+    #[test]
+    fn scripted() {
+        let mut t = ScriptedTieBreaker::new(vec![1, 0, 5]);
+        assert_eq!(t.choose(&[3, 7]), Ok(7));
+        assert_eq!(t.choose(&[3, 7]), Ok(3));
+        assert!(t.choose(&[3, 7]).is_err());
+        assert!(t.choose(&[3, 7]).is_err());
+    }
+}
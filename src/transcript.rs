@@ -0,0 +1,201 @@
+//! Count-by-count record of the distribution of preferences.
+
+use candidate::CandidateId;
+use util::*;
+pub use vote_map::ExclusionRule;
+
+/// The distribution of preferences, one entry per count.
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    /// Total number of formal votes, which every count must account for.
+    pub num_votes: u32,
+    pub counts: Vec<Count>,
+}
+
+/// What happened at a count.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CountAction {
+    /// Allocation of first preferences (and distribution of any disqualified candidates' papers).
+    FirstPreferences,
+    /// Transfer of an elected candidate's surplus.
+    Surplus {
+        from: CandidateId,
+        transfer_value: Frac,
+    },
+    /// Distribution of the papers excluded candidates hold at one transfer value, as a single
+    /// parcel. More than one candidate's papers are distributed together only after a bulk
+    /// exclusion.
+    Exclusion {
+        from: Vec<CandidateId>,
+        transfer_value: Frac,
+        /// The provision the candidates were excluded under.
+        rule: ExclusionRule,
+    },
+    /// Exclusion leaving two continuing candidates for the last vacancy, which is filled without
+    /// distributing the excluded papers (s273(17)).
+    LastVacancy,
+}
+
+/// Whether a candidate is still in the running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandidateStatus {
+    Continuing,
+    Elected,
+    Excluded,
+}
+
+/// Papers and votes held at a count, with the change since the previous count.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tally {
+    pub papers: u32,
+    pub votes: Int,
+    pub papers_change: i64,
+    pub votes_change: Int,
+}
+
+/// A candidate's standing at a count.
+#[derive(Debug, Clone)]
+pub struct CandidateCount {
+    pub id: CandidateId,
+    pub status: CandidateStatus,
+    pub tally: Tally,
+}
+
+#[derive(Debug, Clone)]
+pub struct Count {
+    /// Count number, starting from 1.
+    pub number: usize,
+    pub action: CountAction,
+    /// Candidates excluded at this count, whose papers begin to be distributed.
+    pub excluded: Vec<CandidateId>,
+    /// Candidates elected at this count, in order of election.
+    pub elected: Vec<CandidateId>,
+    /// Progressive totals for each candidate, in order of candidate ID.
+    pub candidates: Vec<CandidateCount>,
+    /// Papers and votes exhausted so far.
+    pub exhausted: Tally,
+    /// Exact value of the papers exhausted so far, before the votes are rounded down.
+    pub exhausted_value: Frac,
+    /// Votes lost so far by rounding transfers down to whole votes (negative for a gain).
+    pub loss_by_fraction: Tally,
+}
+
+impl Tally {
+    fn new(papers: u32, votes: Int, previous: Option<&Tally>) -> Tally {
+        let (papers_change, votes_change) = match previous {
+            Some(prev) => (
+                i64::from(papers) - i64::from(prev.papers),
+                &votes - &prev.votes,
+            ),
+            None => (i64::from(papers), votes.clone()),
+        };
+        Tally {
+            papers,
+            votes,
+            papers_change,
+            votes_change,
+        }
+    }
+}
+
+impl Count {
+    /// Check that the candidates' votes, exhausted votes and loss by fraction add up to the total
+    /// number of formal votes.
+    pub fn check_conservation(&self, num_votes: u32) -> Result<(), String> {
+        let candidate_votes = self
+            .candidates
+            .iter()
+            .fold(Int::zero(), |total, c| total + &c.tally.votes);
+        let total = &candidate_votes + &self.exhausted.votes + &self.loss_by_fraction.votes;
+
+        if total == Int::from(num_votes) {
+            Ok(())
+        } else {
+            Err(format!(
+                "votes not conserved at count {}: {} for candidates + {} exhausted + {} lost by \
+                 fraction = {}, but there are {} formal votes",
+                self.number,
+                candidate_votes,
+                self.exhausted.votes,
+                self.loss_by_fraction.votes,
+                total,
+                num_votes
+            ))
+        }
+    }
+}
+
+impl Transcript {
+    pub fn new(num_votes: u32) -> Transcript {
+        Transcript {
+            num_votes,
+            counts: vec![],
+        }
+    }
+
+    /// Record a count, given each candidate's progressive totals in order of candidate ID, and
+    /// the progressive totals of exhausted papers, votes and their exact value, and of votes lost by
+    /// fraction.
+    ///
+    /// Every formal vote must be accounted for, so that a bug in the count is an error rather than
+    /// a silent miscount.
+    pub fn record_count(
+        &mut self,
+        action: CountAction,
+        excluded: Vec<CandidateId>,
+        elected: Vec<CandidateId>,
+        candidates: Vec<(CandidateId, CandidateStatus, u32, Int)>,
+        exhausted: (u32, Int, Frac),
+        loss_by_fraction: Int,
+    ) -> Result<(), String> {
+        let number = self.counts.len() + 1;
+        let previous = self.counts.last();
+
+        let prev_tally = |idx: usize| previous.map(|count: &Count| &count.candidates[idx].tally);
+
+        let candidates = candidates
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (id, status, papers, votes))| CandidateCount {
+                id,
+                status,
+                tally: Tally::new(papers, votes, prev_tally(idx)),
+            })
+            .collect();
+
+        let (exhausted_papers, exhausted_votes, exhausted_value) = exhausted;
+        let exhausted = Tally::new(
+            exhausted_papers,
+            exhausted_votes,
+            previous.map(|count| &count.exhausted),
+        );
+        let loss_by_fraction = Tally::new(
+            0,
+            loss_by_fraction,
+            previous.map(|count| &count.loss_by_fraction),
+        );
+
+        let count = Count {
+            number,
+            action,
+            excluded,
+            elected,
+            candidates,
+            exhausted,
+            exhausted_value,
+            loss_by_fraction,
+        };
+        let result = count.check_conservation(self.num_votes);
+        self.counts.push(count);
+        result
+    }
+
+    /// Record the election of a candidate at the most recent count, without any transfer.
+    pub fn elect_at_last_count(&mut self, id: CandidateId) {
+        let count = unwrap!(self.counts.last_mut());
+        count.elected.push(id);
+        for candidate in count.candidates.iter_mut().filter(|c| c.id == id) {
+            candidate.status = CandidateStatus::Elected;
+        }
+    }
+}
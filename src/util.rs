@@ -4,7 +4,7 @@ use std::io::{self, BufRead, BufReader};
 
 pub use gmp::mpq::Mpq;
 pub use gmp::mpz::Mpz;
-pub use std::collections::{BTreeMap, HashMap};
+pub use std::collections::{BTreeMap, HashMap, HashSet};
 
 pub type Int = Mpz;
 pub type Frac = Mpq;
@@ -1,16 +1,19 @@
+use std::cmp::{min, Ordering};
 use util::*;
 
 /// Record of a candidate's vote tally at each iteration of the algorithm.
 ///
 /// Used primarily to break ties.
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
 pub struct VoteLog {
     log: Vec<Int>,
 }
 
 impl VoteLog {
     pub fn new() -> Self {
-        VoteLog { log: vec![] }
+        VoteLog {
+            log: vec![Int::zero()],
+        }
     }
 
     pub fn update_vote(&mut self, idx: usize, vote: Int) {
@@ -35,6 +38,26 @@ impl VoteLog {
     pub fn latest(&self) -> &Int {
         unwrap!(self.maybe_latest())
     }
+
+    /// Number of counts recorded, which may be fewer than the number conducted if the tally
+    /// hasn't changed recently.
+    pub fn num_counts(&self) -> usize {
+        self.log.len()
+    }
+
+    /// Tally at the count with index `idx`.
+    pub fn tally_at(&self, idx: usize) -> &Int {
+        &self.log[min(idx, self.log.len() - 1)]
+    }
+
+    /// Compare tallies count by count, starting from the first.
+    pub fn cmp_forward(&self, other: &VoteLog) -> Ordering {
+        let num_counts = self.num_counts().max(other.num_counts());
+        (0..num_counts)
+            .map(|idx| self.tally_at(idx).cmp(other.tally_at(idx)))
+            .find(|&ord| ord != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
 }
 
 #[cfg(test)]
@@ -63,5 +86,24 @@ mod test {
         assert!(v1 < v2);
         assert!(v1 != v2);
         assert!(v2 > v1);
+        assert_eq!(v1.cmp_forward(&v2), Ordering::Less);
+    }
+
+    #[test]
+    fn tally_at() {
+        // v = [0, 0, 4, 4, 4] with the last two counts implicit.
+        let mut v = VoteLog::new();
+        v.update_vote(2, Int::from(4));
+        assert_eq!(v.num_counts(), 3);
+        assert_eq!(v.tally_at(0), &Int::from(0));
+        assert_eq!(v.tally_at(2), &Int::from(4));
+        assert_eq!(v.tally_at(4), &Int::from(4));
+
+        // Implicit counts compare equal to explicit ones.
+        let mut w = VoteLog::new();
+        w.update_vote(2, Int::from(4));
+        w.update_vote(4, Int::from(0));
+        assert!(v != w);
+        assert_eq!(v.cmp_forward(&w), Ordering::Equal);
     }
 }
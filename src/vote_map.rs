@@ -1,203 +1,804 @@
 use arith::*;
 use ballot::*;
 use candidate::*;
-use stats::Stats;
+use countback::QuotaPapers;
+use rules::*;
+use tie_break::*;
+use transcript::CandidateStatus;
 use util::*;
 use vote_log::*;
 
 use itertools::Itertools;
-use rand::{thread_rng, Rng};
+use std::cmp::Ordering;
 use std::mem;
 
-/// Map from transfer values to ballots with that transfer value.
-pub type TransferMap<'a> = BTreeMap<Frac, Vec<&'a mut Ballot>>;
+/// Map from transfer values to papers with that transfer value.
+pub type TransferMap<P> = BTreeMap<Frac, Vec<P>>;
+
+/// Handle to ballot papers that a candidate holds and transfers together: a single ballot of an
+/// arena, or a branch of a ballot trie standing for every paper that shares a sequence of
+/// preferences. The preferences themselves are kept in the store.
+pub trait Papers: Copy {
+    /// Where the preferences of the papers are kept.
+    type Store;
+
+    /// Number of papers.
+    fn weight(&self, store: &Self::Store) -> u32;
+
+    /// The candidate whose preference the papers are currently at.
+    fn candidate(&self, store: &Self::Store) -> CandidateId;
+
+    /// Move the papers on to their next preference for a continuing candidate, which may split
+    /// them. Returns the number of papers that exhaust instead.
+    fn next_preferences<F>(
+        self,
+        store: &Self::Store,
+        is_continuing: F,
+        next: &mut Vec<Self>,
+    ) -> u32
+    where
+        F: Fn(CandidateId) -> bool;
+
+    /// Split the papers of an elected candidate into transfers of their surplus, according to
+    /// the rules.
+    fn surplus_transfers(
+        rules: &dyn CountingRules,
+        surplus: Surplus<Self>,
+        store: &Self::Store,
+    ) -> Result<Vec<PreferenceTransfer<Self>>, String>;
+
+    /// The papers an elected candidate held and the transfers of their surplus, from which their
+    /// quota can be rebuilt for a countback, or `None` if the papers aren't held one by one.
+    fn quota_papers(
+        held: &TransferMap<Self>,
+        transfers: &[PreferenceTransfer<Self>],
+    ) -> Option<QuotaPapers>;
+}
+
+impl Papers for Paper {
+    type Store = BallotArena;
+
+    fn weight(&self, ballots: &BallotArena) -> u32 {
+        ballots.weight(self.index())
+    }
+
+    fn candidate(&self, ballots: &BallotArena) -> CandidateId {
+        ballots.prefs(self.index())[self.current()]
+    }
+
+    fn next_preferences<F>(
+        self,
+        ballots: &BallotArena,
+        is_continuing: F,
+        next: &mut Vec<Self>,
+    ) -> u32
+    where
+        F: Fn(CandidateId) -> bool,
+    {
+        let start = self.current();
+        let prefs = ballots.prefs(self.index());
+        match prefs[start..].iter().position(|&id| is_continuing(id)) {
+            Some(offset) => {
+                next.push(self.at(start + offset));
+                0
+            }
+            None => ballots.weight(self.index()),
+        }
+    }
+
+    fn surplus_transfers(
+        rules: &dyn CountingRules,
+        surplus: Surplus<Self>,
+        ballots: &BallotArena,
+    ) -> Result<Vec<PreferenceTransfer<Self>>, String> {
+        Ok(rules.surplus_transfers(surplus, ballots))
+    }
+
+    fn quota_papers(
+        held: &TransferMap<Self>,
+        transfers: &[PreferenceTransfer<Self>],
+    ) -> Option<QuotaPapers> {
+        Some(QuotaPapers {
+            held: held.clone(),
+            transferred: transfers
+                .iter()
+                .map(|PreferenceTransfer(_, value, papers)| (value.clone(), papers.clone()))
+                .collect(),
+        })
+    }
+}
 
 /// Intermediate data structure mapping candidates to ballots.
-pub struct VoteMap<'a> {
-    info: HashMap<CandidateId, VoteInfo<'a>>,
+///
+/// Candidates are numbered by their position in ascending order of ID, and everything about them
+/// is held in vectors by that index, so that looking a candidate up needs no hashing and they are
+/// always visited in the same order.
+pub struct VoteMap<'a, P: Papers> {
+    /// Candidate IDs, in ascending order.
+    ids: Vec<CandidateId>,
+    /// Index of each candidate ID, or `NOT_STANDING` for IDs of candidates not in the count.
+    index: Vec<usize>,
+    info: Vec<VoteInfo<P>>,
+    /// Candidates who have been elected or excluded.
+    eliminated: CandidateSet,
+    elected: CandidateSet,
     candidates: &'a CandidateMap,
+    /// Where the preferences of the papers are kept.
+    store: &'a P::Store,
     one: Frac,
+    tie_history: TieHistory,
+    ties: Vec<Tie>,
 }
 
 /// Per-candidate intermediate data.
-struct VoteInfo<'a> {
+#[derive(Clone)]
+struct VoteInfo<P> {
     votes: VoteLog,
-    ballots: TransferMap<'a>,
-    eliminated: bool,
+    ballots: TransferMap<P>,
+    /// Number of ballot papers held, including those awaiting transfer to another candidate.
+    papers: u32,
+    /// Where the papers received at the most recent count begin.
+    last_parcel: LastParcel,
+}
+
+const NOT_STANDING: usize = usize::MAX;
+
+/// A set of candidates by index, stored as a bitset.
+#[derive(Clone)]
+struct CandidateSet {
+    words: Vec<u64>,
+}
+
+impl CandidateSet {
+    fn new(num_candidates: usize) -> Self {
+        CandidateSet {
+            words: vec![0; num_candidates.div_ceil(64)],
+        }
+    }
+
+    fn contains(&self, idx: usize) -> bool {
+        self.words[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    fn insert(&mut self, idx: usize) {
+        self.words[idx / 64] |= 1 << (idx % 64);
+    }
 }
 
-pub struct PreferenceTransfer<'a>(pub CandidateId, pub Frac, pub Vec<&'a mut Ballot>);
+/// Papers and votes exhausted by a transfer, and the votes it lost by rounding down.
+#[derive(Debug, Clone)]
+pub struct TransferOutcome {
+    pub exhausted_papers: u32,
+    /// Exact value of the exhausted papers, of which the whole votes are exhausted.
+    pub exhausted_value: Frac,
+    pub exhausted_votes: Int,
+    pub loss_by_fraction: Int,
+}
+
+#[derive(Clone)]
+pub struct PreferenceTransfer<P>(pub CandidateId, pub Frac, pub Vec<P>);
+
+/// The start of the parcel of papers most recently received by a candidate, which runs to the end
+/// of the papers held at its transfer value.
+///
+/// Candidates who have only received first preferences (and papers of disqualified candidates)
+/// have all of their papers at a transfer value of 1 as their last parcel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LastParcel {
+    pub transfer_value: Frac,
+    /// Index of the first paper of the parcel among those held at `transfer_value`.
+    pub start: usize,
+}
+
+impl Default for LastParcel {
+    fn default() -> Self {
+        LastParcel {
+            transfer_value: Frac::one(),
+            start: 0,
+        }
+    }
+}
+
+/// The papers of an elected candidate, from which their surplus is transferred.
+pub struct Surplus<P> {
+    pub candidate: CandidateId,
+    /// The candidate's votes when elected.
+    pub votes: Int,
+    pub quota: Frac,
+    /// Each transfer value at which the candidate received papers, mapped to those papers.
+    pub papers: TransferMap<P>,
+    pub last_parcel: LastParcel,
+    /// Candidates still in the count, to whom papers may be transferred.
+    pub continuing: HashSet<CandidateId>,
+}
+
+impl<P: Papers> Surplus<P> {
+    /// The surplus of a candidate elected with `votes`, whose papers all have their last parcel
+    /// at a transfer value of 1 and no continuing candidates recorded.
+    pub fn new(candidate: CandidateId, votes: Int, quota: Frac, papers: TransferMap<P>) -> Self {
+        Surplus {
+            candidate,
+            votes,
+            quota,
+            papers,
+            last_parcel: LastParcel::default(),
+            continuing: HashSet::new(),
+        }
+    }
+
+    /// Number of votes in excess of the quota.
+    pub fn amount(&self) -> Frac {
+        Frac::from(&self.votes) - &self.quota
+    }
+
+    /// Remove the papers of the last parcel from `papers`.
+    pub fn take_last_parcel(&mut self) -> Vec<P> {
+        let start = self.last_parcel.start;
+        match self.papers.get_mut(&self.last_parcel.transfer_value) {
+            Some(ref mut bucket) if start <= bucket.len() => bucket.split_off(start),
+            _ => vec![],
+        }
+    }
+
+    /// The number of papers held at each transfer value, from which rules that transfer whole
+    /// parcels plan the transfer.
+    pub fn tally(&self, store: &P::Store) -> SurplusTally {
+        SurplusTally {
+            candidate: self.candidate,
+            votes: self.votes.clone(),
+            quota: self.quota.clone(),
+            papers: self
+                .papers
+                .iter()
+                .map(|(value, papers)| {
+                    let num_papers = papers.iter().map(|p| p.weight(store)).sum();
+                    (value.clone(), num_papers)
+                })
+                .collect(),
+        }
+    }
+
+    /// Carry out a plan, transferring all the papers held at each group of transfer values.
+    pub fn split(mut self, plan: SurplusPlan) -> Vec<PreferenceTransfer<P>> {
+        plan.into_iter()
+            .map(|(values, transfer_value)| {
+                let papers = values
+                    .iter()
+                    .flat_map(|value| self.papers.remove(value).unwrap_or_default())
+                    .collect();
+                PreferenceTransfer(self.candidate, transfer_value, papers)
+            })
+            .collect()
+    }
+}
+
+impl Surplus<Paper> {
+    /// The continuing candidate that a paper would be transferred to next, if any.
+    pub fn next_preference(&self, paper: Paper, ballots: &BallotArena) -> Option<CandidateId> {
+        ballots.prefs(paper.index())[paper.current()..]
+            .iter()
+            .cloned()
+            .find(|id| self.continuing.contains(id))
+    }
+}
 
-pub struct CandidateElected<'a> {
+/// An elected candidate's votes, and the number of papers they hold at each transfer value.
+#[derive(Debug, Clone)]
+pub struct SurplusTally {
+    pub candidate: CandidateId,
+    pub votes: Int,
+    pub quota: Frac,
+    pub papers: BTreeMap<Frac, u32>,
+}
+
+impl SurplusTally {
+    /// Number of votes in excess of the quota.
+    pub fn amount(&self) -> Frac {
+        Frac::from(&self.votes) - &self.quota
+    }
+}
+
+/// The transfers of a surplus, each made up of every paper held at one or more transfer values,
+/// which are transferred at the value given.
+pub type SurplusPlan = Vec<(Vec<Frac>, Frac)>;
+
+pub struct CandidateElected<P> {
     pub id: CandidateId,
     pub votes: Int,
-    pub transfers: Vec<PreferenceTransfer<'a>>,
+    pub transfers: Vec<PreferenceTransfer<P>>,
+    /// The papers making up the candidate's quota, if they were elected with one and the papers
+    /// are held one by one.
+    pub quota_papers: Option<QuotaPapers>,
 }
 
-pub struct CandidateExcluded<'a> {
+pub struct CandidateExcluded<P> {
     pub id: CandidateId,
-    pub transfers: Vec<PreferenceTransfer<'a>>,
+    pub rule: ExclusionRule,
+    pub transfers: Vec<PreferenceTransfer<P>>,
+}
+
+/// The provision under which a candidate was excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExclusionRule {
+    /// Excluded before the count began (e.g. ruled ineligible by the Court of Disputed Returns).
+    Disqualified,
+    /// Sole exclusion of the candidate standing lowest in the poll, section 273(13).
+    Lowest,
+    /// Bulk exclusion of candidates whose notional votes fall short of the leading shortfall,
+    /// section 273(13A)(a).
+    LeadingShortfall,
+    /// Bulk exclusion of candidates whose notional votes fall short of the vacancy shortfall,
+    /// section 273(13A)(b) and (c).
+    VacancyShortfall,
 }
 
-impl<'a> VoteInfo<'a> {
+impl<P> VoteInfo<P> {
     fn new() -> Self {
         VoteInfo {
             votes: VoteLog::new(),
             ballots: new_transfer_map(),
-            eliminated: false,
+            papers: 0,
+            last_parcel: LastParcel::default(),
         }
     }
 
-    fn take_ballots(&mut self) -> TransferMap<'a> {
+    fn take_ballots(&mut self) -> TransferMap<P> {
         mem::replace(&mut self.ballots, new_transfer_map())
     }
 }
 
-fn new_transfer_map<'a>() -> TransferMap<'a> {
+fn new_transfer_map<P>() -> TransferMap<P> {
     let mut map = TransferMap::new();
     map.insert(frac!(1u64), vec![]);
     map
 }
 
-impl<'a> VoteMap<'a> {
-    pub fn new(candidates: &'a CandidateMap) -> Result<VoteMap<'a>, String> {
-        let mut v = VoteMap {
-            info: HashMap::new(),
-            candidates,
-            one: frac!(1u64),
-        };
-        for &id in candidates.keys() {
-            let prev = v.info.insert(id, VoteInfo::new());
-            if prev.is_some() {
+// Not derived, as that would require the store to be `Clone` too, when only the reference to it
+// is copied.
+impl<'a, P: Papers> Clone for VoteMap<'a, P> {
+    fn clone(&self) -> Self {
+        VoteMap {
+            ids: self.ids.clone(),
+            index: self.index.clone(),
+            info: self.info.clone(),
+            eliminated: self.eliminated.clone(),
+            elected: self.elected.clone(),
+            candidates: self.candidates,
+            store: self.store,
+            one: self.one.clone(),
+            tie_history: self.tie_history,
+            ties: self.ties.clone(),
+        }
+    }
+}
+
+impl<'a, P: Papers> VoteMap<'a, P> {
+    pub fn new(
+        candidates: &'a CandidateMap,
+        store: &'a P::Store,
+        tie_history: TieHistory,
+    ) -> Result<VoteMap<'a, P>, String> {
+        let ids: Vec<CandidateId> = candidates.keys().cloned().sorted();
+        let num_ids = ids.last().map_or(0, |&id| usize::from(id) + 1);
+        let mut index = vec![NOT_STANDING; num_ids];
+        for (idx, &id) in ids.iter().enumerate() {
+            if index[usize::from(id)] != NOT_STANDING {
                 return Err(format!("Candidate ID {} appears more than once", id));
             }
+            index[usize::from(id)] = idx;
         }
-        debug_assert_eq!(v.info.len(), candidates.len());
-        Ok(v)
+
+        Ok(VoteMap {
+            info: ids.iter().map(|_| VoteInfo::new()).collect(),
+            eliminated: CandidateSet::new(ids.len()),
+            elected: CandidateSet::new(ids.len()),
+            ids,
+            index,
+            candidates,
+            store,
+            one: frac!(1u64),
+            tie_history,
+            ties: vec![],
+        })
+    }
+
+    /// Number of candidates in the count, including those elected or excluded.
+    pub fn num_candidates(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Index of a candidate, from 0 to `num_candidates() - 1` in ascending order of ID.
+    ///
+    /// Panics if `id` is not the `CandidateId` of a candidate in the count.
+    pub fn index_of(&self, id: CandidateId) -> usize {
+        let idx = self.index.get(usize::from(id)).cloned();
+        match idx {
+            Some(idx) if idx != NOT_STANDING => idx,
+            _ => panic!("Candidate not found: {}", id),
+        }
+    }
+
+    /// Where the preferences of the papers are kept.
+    pub fn store(&self) -> &'a P::Store {
+        self.store
+    }
+
+    /// ID of the candidate with an index.
+    pub fn id_at(&self, idx: usize) -> CandidateId {
+        self.ids[idx]
+    }
+
+    fn info(&self, id: CandidateId) -> &VoteInfo<P> {
+        &self.info[self.index_of(id)]
+    }
+
+    fn info_mut(&mut self, id: CandidateId) -> &mut VoteInfo<P> {
+        let idx = self.index_of(id);
+        &mut self.info[idx]
+    }
+
+    /// Mark a candidate as elected, which also takes them out of the count.
+    fn mark_elected(&mut self, id: CandidateId) {
+        let idx = self.index_of(id);
+        self.eliminated.insert(idx);
+        self.elected.insert(idx);
     }
 
-    /// Add votes to a candidate's tally according to the weight and current preference of a ballot.
-    pub fn add(&mut self, idx: usize, ballot: &'a mut Ballot) {
-        let candidate = ballot.prefs[ballot.current()];
+    /// Add votes to a candidate's tally according to the weight and current preference of some
+    /// papers.
+    pub fn add(&mut self, idx: usize, papers: P) {
+        let candidate = papers.candidate(self.store);
 
-        let all_info = &mut self.info;
-        let info = all_info.get_mut(&candidate).expect("Candidate not found");
+        let position = self.index_of(candidate);
+        let info = &mut self.info[position];
 
         // Add to the candidate's tally.
-        info.votes.update_vote(idx, Int::from(ballot.weight()));
+        let weight = papers.weight(self.store);
+        info.votes.update_vote(idx, Int::from(weight));
+        info.papers += weight;
 
-        // Add the ballot to the appropriate bucket.
+        // Add the papers to the appropriate bucket.
         let bucket = info.ballots.get_mut(&self.one).unwrap();
-        bucket.push(ballot);
+        bucket.push(papers);
     }
 
-    /// Get the IDs of all candidates whose vote exceeds the quota.
-    pub fn get_candidates_with_quota(&self, quota: &Int) -> Vec<CandidateId> {
+    /// Get the IDs of all candidates whose vote exceeds the quota, in order of election.
+    ///
+    /// Candidates with more votes are elected first. Ties are settled on votes at earlier counts,
+    /// or failing that by the tie breaker, and are recorded.
+    pub fn get_candidates_with_quota(
+        &mut self,
+        quota: &Int,
+        tie_breaker: &mut dyn TieBreaker,
+    ) -> Result<Vec<CandidateId>, String> {
         let mut candidates_with_quota = self
-            .info
-            .iter()
-            .filter(|&(_, info)| !info.eliminated)
-            .map(|(id, info)| (id, info.votes.latest()))
-            .filter(|&(_, votes)| votes >= quota)
+            .candidates_remaining()
+            .map(|(id, info)| (id, info.votes.latest().clone()))
+            .filter(|(_, votes)| votes >= quota)
             .collect::<Vec<_>>();
 
         // Sort by vote descending.
-        candidates_with_quota.sort_by(|&(_, v1), &(_, v2)| v1.cmp(v2).reverse());
+        candidates_with_quota.sort_by(|(c1, v1), (c2, v2)| v1.cmp(v2).reverse().then(c1.cmp(c2)));
+
+        let groups: Vec<Vec<_>> = candidates_with_quota
+            .into_iter()
+            .group_by(|(_, votes)| votes.clone())
+            .into_iter()
+            .map(|(_, equal_votes)| equal_votes.map(|(c, _)| c).collect())
+            .collect();
 
-        candidates_with_quota.into_iter().map(|(c, _)| *c).collect()
+        let mut ordered = vec![];
+        for group in groups {
+            if group.len() == 1 {
+                ordered.extend(group);
+            } else {
+                ordered.extend(self.order_of_election(group, tie_breaker)?);
+            }
+        }
+        Ok(ordered)
+    }
+
+    /// Determine the order of election of candidates who reach the quota with equal votes.
+    ///
+    /// The first elected is the candidate with the most votes at earlier counts, per section
+    /// 273(22), or if that doesn't separate them, the tie breaker's choice. This is repeated for
+    /// the rest.
+    fn order_of_election(
+        &mut self,
+        tied: Vec<CandidateId>,
+        tie_breaker: &mut dyn TieBreaker,
+    ) -> Result<Vec<CandidateId>, String> {
+        let mut remaining = tied;
+        let mut order = vec![];
+
+        while remaining.len() > 1 {
+            let mut ordered = remaining.clone();
+            let deciding_count = self.order_by_earlier_counts(&mut ordered);
+
+            let highest = *unwrap!(ordered.last());
+            let second = ordered[ordered.len() - 2];
+
+            let (chosen, settled_by) = match (self.tie_history, deciding_count) {
+                (TieHistory::Backward, Some(idx)) => {
+                    (highest, TieSettlement::EarlierCount(idx + 1))
+                }
+                (TieHistory::Forward, _) => {
+                    let (h, s) = (&self.info(highest).votes, &self.info(second).votes);
+                    match self.separating_count(h, s) {
+                        Some(idx) => (highest, TieSettlement::EarlierCount(idx + 1)),
+                        None => {
+                            let level: Vec<_> = remaining
+                                .iter()
+                                .cloned()
+                                .filter(|&c| self.info(c).votes.cmp_forward(h) == Ordering::Equal)
+                                .sorted();
+                            (tie_breaker.choose(&level)?, TieSettlement::TieBreaker)
+                        }
+                    }
+                }
+                (TieHistory::Backward, None) => {
+                    (tie_breaker.choose(&remaining)?, TieSettlement::TieBreaker)
+                }
+            };
+
+            self.ties.push(Tie {
+                count: 0,
+                kind: TieKind::OrderOfElection,
+                candidates: remaining.clone(),
+                chosen,
+                settled_by,
+            });
+
+            order.push(chosen);
+            remaining.retain(|&c| c != chosen);
+        }
+
+        order.extend(remaining);
+        Ok(order)
+    }
+
+    /// First count at which two candidates had different votes.
+    fn separating_count(&self, v1: &VoteLog, v2: &VoteLog) -> Option<usize> {
+        let num_counts = v1.num_counts().max(v2.num_counts());
+        (0..num_counts).find(|&idx| v1.tally_at(idx) != v2.tally_at(idx))
     }
 
     /// Get the ID of the candidate with the least votes.
-    pub fn get_last_candidate(&self) -> CandidateId {
-        let mut sorted_candidates: Vec<_> = self.candidates_remaining().collect();
-        sorted_candidates.sort_by_key(|&(_, info)| info.votes.latest());
+    ///
+    /// Any tie is recorded, and can be retrieved with `take_ties`.
+    pub fn get_last_candidate(
+        &mut self,
+        tie_breaker: &mut dyn TieBreaker,
+    ) -> Result<CandidateId, String> {
+        let min_candidates: Vec<_> = {
+            let mut sorted_candidates: Vec<_> = self.candidates_remaining().collect();
+            sorted_candidates.sort_by_key(|&(_, info)| info.votes.latest());
 
-        let min_vote = sorted_candidates[0].1.votes.latest().clone();
+            let min_vote = sorted_candidates[0].1.votes.latest().clone();
 
-        // Collect all candidates with the minimum vote.
-        let min_candidates: Vec<_> = sorted_candidates
-            .into_iter()
-            .take_while(|&(_, info)| info.votes.latest() == &min_vote)
-            .collect();
+            // Collect all candidates with the minimum vote.
+            sorted_candidates
+                .into_iter()
+                .take_while(|&(_, info)| info.votes.latest() == &min_vote)
+                .map(|(candidate, _)| candidate)
+                .sorted()
+        };
 
         if min_candidates.len() == 1 {
-            let (candidate, _) = min_candidates[0];
-            return candidate;
+            return Ok(min_candidates[0]);
         }
 
         // Try to break the tie based on past tallies.
-        let historical_min = min_candidates
-            .iter()
-            .map(|&(_, info)| &info.votes)
-            .min()
-            .unwrap();
-
-        let hist_min_candidates: Vec<_> = min_candidates
-            .into_iter()
-            .filter(|&(_, info)| &info.votes == historical_min)
-            .map(|(candidate, _)| candidate)
-            .sorted();
+        let mut ordered = min_candidates.clone();
+        let (chosen, settled_by) = match self.order_by_earlier_counts(&mut ordered) {
+            Some(idx) => (ordered[0], TieSettlement::EarlierCount(idx + 1)),
+            None => {
+                let still_tied = match self.tie_history {
+                    // No count had all of them unequal, so the draw is between all of them.
+                    TieHistory::Backward => min_candidates.clone(),
+                    // Draw from the candidates who were tied with the lowest at every count.
+                    TieHistory::Forward => {
+                        let lowest = &self.info(ordered[0]).votes;
+                        ordered
+                            .iter()
+                            .cloned()
+                            .filter(|&c| self.info(c).votes.cmp_forward(lowest) == Ordering::Equal)
+                            .sorted()
+                    }
+                };
+                (tie_breaker.choose(&still_tied)?, TieSettlement::TieBreaker)
+            }
+        };
 
-        if hist_min_candidates.len() == 1 {
-            return hist_min_candidates[0];
-        }
+        self.ties.push(Tie {
+            count: 0,
+            kind: TieKind::Exclusion,
+            candidates: min_candidates,
+            chosen,
+            settled_by,
+        });
 
-        // TODO: ideally we would fork the execution and check that the final result
-        // is the same for all possible exclusion choices, but for now we'll just choose
-        // randomly...
-        *thread_rng().choose(&hist_min_candidates).unwrap()
+        Ok(chosen)
     }
 
-    pub fn find_next_valid_preference(&self, b: &Ballot) -> Option<usize> {
-        for (i, cand) in b.prefs[b.current()..].iter().enumerate() {
-            if !self.info[cand].eliminated {
-                return Some(b.current() + i);
+    /// Sort candidates with equal votes in ascending order of their votes at earlier counts.
+    ///
+    /// Under the statutory rule of section 273(22) they are ordered by their votes at the most
+    /// recent count at which they all had unequal votes. Under the forward rule they are compared
+    /// count by count starting from the first.
+    ///
+    /// Returns the index of the count which separates the lowest candidate from the rest, or
+    /// `None` if there is no such count.
+    fn order_by_earlier_counts(&self, tied: &mut [CandidateId]) -> Option<usize> {
+        let votes = |c: &CandidateId| &self.info(*c).votes;
+        let num_counts = tied
+            .iter()
+            .map(|c| votes(c).num_counts())
+            .max()
+            .unwrap_or(0);
+
+        match self.tie_history {
+            TieHistory::Backward => {
+                let deciding_count = (0..num_counts).rev().find(|&idx| {
+                    let tallies: Vec<_> = tied.iter().map(|c| votes(c).tally_at(idx)).sorted();
+                    tallies.windows(2).all(|pair| pair[0] != pair[1])
+                });
+                if let Some(idx) = deciding_count {
+                    tied.sort_by(|c1, c2| votes(c1).tally_at(idx).cmp(votes(c2).tally_at(idx)));
+                }
+                deciding_count
+            }
+            TieHistory::Forward => {
+                tied.sort_by(|c1, c2| votes(c1).cmp_forward(votes(c2)));
+                self.separating_count(votes(&tied[0]), votes(&tied[1]))
             }
         }
-        None
+    }
+
+    /// Take the ties recorded since the last call, with their `count` left for the caller to set.
+    pub fn take_ties(&mut self) -> Vec<Tie> {
+        mem::take(&mut self.ties)
+    }
+
+    pub fn is_continuing(&self, candidate: CandidateId) -> bool {
+        !self.eliminated.contains(self.index_of(candidate))
     }
 
     pub fn num_candidates_remaining(&self) -> usize {
         self.candidates_remaining().count()
     }
 
-    fn candidates_remaining<'b>(&'b self) -> impl Iterator<Item = (CandidateId, &'b VoteInfo<'a>)> {
+    fn candidates_remaining(&self) -> impl Iterator<Item = (CandidateId, &VoteInfo<P>)> {
         self.info
             .iter()
-            .filter(|&(_, info)| !info.eliminated)
-            .map(|(id, info)| (*id, info))
+            .enumerate()
+            .filter(move |&(idx, _)| !self.eliminated.contains(idx))
+            .map(move |(idx, info)| (self.ids[idx], info))
     }
 
-    pub fn elect_remaining(self) -> Vec<CandidateElected<'a>> {
-        self.info
-            .into_iter()
-            .filter(|&(_, ref info)| !info.eliminated)
+    /// Elect all the continuing candidates, in order of votes (descending).
+    pub fn elect_remaining(&self) -> Vec<CandidateElected<P>> {
+        let mut elected: Vec<_> = self
+            .candidates_remaining()
             .map(|(id, info)| CandidateElected {
                 id,
                 votes: info.votes.latest().clone(),
                 transfers: vec![],
+                quota_papers: None,
             })
-            .collect()
+            .collect();
+        elected.sort_by(|c1, c2| c2.votes.cmp(&c1.votes).then(c1.id.cmp(&c2.id)));
+        elected
+    }
+
+    /// Elect the continuing candidate with the most votes, regardless of the quota.
+    ///
+    /// Used to fill the last vacancy when two candidates remain, per section 273(17), with a tie
+    /// settled by the tie breaker (the Australian Electoral Officer's casting vote).
+    pub fn elect_highest(
+        &mut self,
+        tie_breaker: &mut dyn TieBreaker,
+    ) -> Result<CandidateElected<P>, String> {
+        let (max_candidates, max_vote) = {
+            let max_vote = unwrap!(self
+                .candidates_remaining()
+                .map(|(_, info)| info.votes.latest())
+                .max())
+            .clone();
+            let max_candidates: Vec<_> = self
+                .candidates_remaining()
+                .filter(|&(_, info)| info.votes.latest() == &max_vote)
+                .map(|(id, _)| id)
+                .sorted();
+            (max_candidates, max_vote)
+        };
+
+        let chosen = if max_candidates.len() == 1 {
+            max_candidates[0]
+        } else {
+            let chosen = tie_breaker.choose(&max_candidates)?;
+            self.ties.push(Tie {
+                count: 0,
+                kind: TieKind::LastVacancy,
+                candidates: max_candidates,
+                chosen,
+                settled_by: TieSettlement::TieBreaker,
+            });
+            chosen
+        };
+
+        self.mark_elected(chosen);
+
+        Ok(CandidateElected {
+            id: chosen,
+            votes: max_vote,
+            transfers: vec![],
+            quota_papers: None,
+        })
     }
 
     pub fn transfer_preferences(
         &mut self,
         idx: usize,
-        transfer: PreferenceTransfer<'a>,
-        stats: &mut Stats,
-    ) {
-        let PreferenceTransfer(_, transfer_val, all_ballots) = transfer;
+        transfer: PreferenceTransfer<P>,
+    ) -> TransferOutcome {
+        self.transfer_merged_preferences(idx, vec![transfer])
+    }
 
-        let grouped_ballots =
-            group_ballots_by_candidate(self, all_ballots, idx, &transfer_val, stats);
+    /// Transfer the papers of several candidates at one transfer value as a single parcel, as for
+    /// candidates excluded in bulk.
+    ///
+    /// Each candidate gives up their own papers, but the votes each continuing candidate receives
+    /// are rounded down once, for the whole parcel.
+    pub fn transfer_merged_preferences(
+        &mut self,
+        idx: usize,
+        transfers: Vec<PreferenceTransfer<P>>,
+    ) -> TransferOutcome {
+        let store = self.store;
+        let transfer_val = transfers[0].1.clone();
+        let mut num_papers = 0;
+        let mut value = Int::zero();
+        let mut all_ballots = vec![];
 
-        for (continuing_id, ballots) in grouped_ballots {
-            let mut info = self.info.get_mut(&continuing_id).unwrap();
+        // Take the papers and their value from the candidates they're transferred from.
+        for PreferenceTransfer(source_id, source_val, ballots) in transfers {
+            assert_eq!(source_val, transfer_val);
+            let source_papers: u32 = ballots.iter().map(|p| p.weight(store)).sum();
+            let source_elected = self.elected.contains(self.index_of(source_id));
+            let source = self.info_mut(source_id);
+            source.papers -= source_papers;
 
-            assert!(!info.eliminated);
+            // An excluded candidate's votes go with their last papers. An elected candidate keeps
+            // a quota.
+            let source_value = if source.papers == 0 && !source_elected {
+                source.votes.latest().clone()
+            } else {
+                ballot_value(&transfer_val, &ballots, store)
+            };
+            source.votes.update_vote(idx, -source_value.clone());
 
-            let incr = ballot_value(&transfer_val, &ballots);
+            num_papers += source_papers;
+            value += source_value;
+            all_ballots.extend(ballots);
+        }
+
+        let grouped_ballots = group_ballots_by_candidate(self, all_ballots);
+
+        let mut papers_transferred = 0;
+        let mut votes_transferred = Int::zero();
+
+        for (continuing_id, ballots) in grouped_ballots {
+            assert!(self.is_continuing(continuing_id));
+            let position = self.index_of(continuing_id);
+            let info = &mut self.info[position];
+
+            let papers: u32 = ballots.iter().map(|p| p.weight(store)).sum();
+            let incr = ballot_value(&transfer_val, &ballots, store);
             info.votes.update_vote(idx, incr.clone());
+            info.papers += papers;
+            papers_transferred += papers;
+            votes_transferred += &incr;
             if !incr.is_zero() {
                 trace!(
                     "+{:?} votes for {:?}, brings total to {:?}",
@@ -211,60 +812,126 @@ impl<'a> VoteMap<'a> {
                 .ballots
                 .entry(transfer_val.clone())
                 .or_insert_with(Vec::new);
+            // Papers of disqualified candidates are treated as part of the first preferences.
+            if idx > 0 {
+                info.last_parcel = LastParcel {
+                    transfer_value: transfer_val.clone(),
+                    start: bucket.len(),
+                };
+            }
             bucket.extend(ballots);
         }
-    }
-
-    pub fn elect_candidates_with_quota(&mut self, quota: &Int) -> Vec<CandidateElected<'a>> {
-        let candidates = self.get_candidates_with_quota(quota);
-        let mut elected = vec![];
 
-        for candidate in candidates {
-            let info = self.info.get_mut(&candidate).unwrap();
+        // Exhausted papers are rounded down like any other parcel, and whatever is left of the
+        // value taken from the source is lost by fraction.
+        let exhausted_papers = num_papers - papers_transferred;
+        let exhausted_value = &transfer_val * frac!(exhausted_papers);
+        let exhausted_votes = exhausted_value.floor();
+        let loss_by_fraction = value - votes_transferred - &exhausted_votes;
+        trace!("Lost {:?} votes by fraction", loss_by_fraction);
 
-            // Mark eliminated.
-            info.eliminated = true;
+        TransferOutcome {
+            exhausted_papers,
+            exhausted_value,
+            exhausted_votes,
+            loss_by_fraction,
+        }
+    }
 
-            let num_votes = info.votes.latest().clone();
+    /// Whether a candidate is continuing, elected or excluded.
+    pub fn status(&self, candidate: CandidateId) -> CandidateStatus {
+        let idx = self.index_of(candidate);
+        match (self.eliminated.contains(idx), self.elected.contains(idx)) {
+            (false, _) => CandidateStatus::Continuing,
+            (true, true) => CandidateStatus::Elected,
+            (true, false) => CandidateStatus::Excluded,
+        }
+    }
 
-            // Create `PreferenceTransfer` events for each transfer value.
-            let transfer_map = info.take_ballots();
+    /// Each candidate's status, papers and votes, in order of candidate ID.
+    pub fn tallies(&self) -> Vec<(CandidateId, CandidateStatus, u32, Int)> {
+        self.ids
+            .iter()
+            .zip(&self.info)
+            .map(|(&id, info)| {
+                (
+                    id,
+                    self.status(id),
+                    info.papers,
+                    info.votes.latest().clone(),
+                )
+            })
+            .collect()
+    }
 
-            // Collect all ballots (erasing existing transfer values).
-            let all_ballots: Vec<_> = transfer_map
-                .into_iter()
-                .flat_map(|(_, ballots)| ballots)
-                .collect();
+    /// Elect the candidates who have reached the quota, up to the number of vacancies remaining,
+    /// and split their papers into transfers of their surpluses.
+    pub fn elect_candidates_with_quota(
+        &mut self,
+        quota: &Frac,
+        positions_remaining: usize,
+        rules: &dyn CountingRules,
+        tie_breaker: &mut dyn TieBreaker,
+    ) -> Result<Vec<CandidateElected<P>>, String> {
+        let mut candidates = self.get_candidates_with_quota(&quota.ceil(), tie_breaker)?;
+        candidates.truncate(positions_remaining);
+        let mut elected = vec![];
 
-            let num_ballots: u32 = all_ballots.iter().map(|b| b.weight()).sum();
+        // All of the candidates reaching the quota are elected before any surplus is transferred.
+        for &candidate in &candidates {
+            self.mark_elected(candidate);
+        }
+        let continuing: HashSet<_> = self.candidates_remaining().map(|(id, _)| id).collect();
 
-            // Aggregate transfer value that accounts for the ones we just threw out...
-            let transfer_value = Frac::ratio(&(&num_votes - quota), &Int::from(num_ballots));
+        for candidate in candidates {
+            let info = self.info_mut(candidate);
+            let num_votes = info.votes.latest().clone();
+            let held = info.take_ballots();
 
-            let pref_transfers = vec![PreferenceTransfer(candidate, transfer_value, all_ballots)];
+            // Create `PreferenceTransfer` events for the surplus.
+            let surplus = Surplus {
+                candidate,
+                votes: num_votes.clone(),
+                quota: quota.clone(),
+                papers: held.clone(),
+                last_parcel: info.last_parcel.clone(),
+                continuing: continuing.clone(),
+            };
+            let pref_transfers = P::surplus_transfers(rules, surplus, self.store)?;
+            let quota_papers = P::quota_papers(&held, &pref_transfers);
 
             elected.push(CandidateElected {
                 id: candidate,
                 votes: num_votes,
                 transfers: pref_transfers,
+                quota_papers,
             });
         }
 
-        elected
+        Ok(elected)
     }
 
     /// Panics if the `id` is not the `CandidateId` of a real candidate.
-    pub fn exclude_candidate_by_id(&mut self, candidate: CandidateId) -> CandidateExcluded<'a> {
-        let info = self.info.get_mut(&candidate).unwrap();
-
-        info.eliminated = true;
+    pub fn exclude_candidate_by_id(
+        &mut self,
+        candidate: CandidateId,
+        rule: ExclusionRule,
+    ) -> CandidateExcluded<P> {
+        let idx = self.index_of(candidate);
+        self.eliminated.insert(idx);
 
-        let transfer_map = info.take_ballots();
+        let transfer_map = self.info[idx].take_ballots();
 
+        // Skip empty parcels (such as the first preferences of a candidate who only received
+        // transfers), but keep one so that the exclusion still takes a count.
         let mut pref_transfers: Vec<_> = transfer_map
             .into_iter()
+            .filter(|(_, ballots)| !ballots.is_empty())
             .map(|(transfer_val, ballots)| PreferenceTransfer(candidate, transfer_val, ballots))
             .collect();
+        if pref_transfers.is_empty() {
+            pref_transfers.push(PreferenceTransfer(candidate, self.one.clone(), vec![]));
+        }
 
         // Reverse the preference transfer events so they're ordered from largest to
         // smallest transfer value.
@@ -272,24 +939,284 @@ impl<'a> VoteMap<'a> {
 
         CandidateExcluded {
             id: candidate,
+            rule,
             transfers: pref_transfers,
         }
     }
 
-    // TODO: bulk exclusions.
-    pub fn exclude_candidates(&mut self) -> Vec<CandidateExcluded<'a>> {
-        let candidate = self.get_last_candidate();
-        vec![self.exclude_candidate_by_id(candidate)]
+    /// Exclude the lowest standing candidate, or several candidates at once if the strategy is
+    /// bulk exclusion and section 273(13A) allows it.
+    pub fn exclude_candidates(
+        &mut self,
+        quota: &Int,
+        positions_remaining: usize,
+        strategy: ExclusionStrategy,
+        tie_breaker: &mut dyn TieBreaker,
+    ) -> Result<Vec<CandidateExcluded<P>>, String> {
+        if strategy == ExclusionStrategy::Bulk {
+            if let Some((to_exclude, rule)) = self.get_bulk_exclusion(quota, positions_remaining) {
+                return Ok(to_exclude
+                    .into_iter()
+                    .map(|id| self.exclude_candidate_by_id(id, rule))
+                    .collect());
+            }
+        }
+        let candidate = self.get_last_candidate(tie_breaker)?;
+        Ok(vec![
+            self.exclude_candidate_by_id(candidate, ExclusionRule::Lowest)
+        ])
+    }
+
+    /// Determine the largest set of candidates that can be excluded in bulk, per section 273(13A).
+    ///
+    /// A candidate's notional vote is their vote plus the votes of every candidate standing lower
+    /// in the poll. A candidate and everyone below them may be excluded together if their notional
+    /// vote is less than the vote of the next highest candidate (so no transfer could lift any of
+    /// them above that candidate), and less than one of the shortfalls:
+    ///
+    /// * The leading shortfall: the votes the highest continuing candidate needs for a quota.
+    /// * The vacancy shortfall: the votes the candidates standing highest in the poll (one per
+    ///   remaining vacancy) need in total for a quota each.
+    ///
+    /// At least as many candidates as there are remaining vacancies must continue afterwards.
+    /// Returns `None` if no more than one candidate could be excluded.
+    pub fn get_bulk_exclusion(
+        &self,
+        quota: &Int,
+        positions_remaining: usize,
+    ) -> Option<(Vec<CandidateId>, ExclusionRule)> {
+        let mut standing: Vec<_> = self
+            .candidates_remaining()
+            .map(|(id, info)| (id, info.votes.latest()))
+            .collect();
+        standing.sort_by(|&(id1, v1), &(id2, v2)| v1.cmp(v2).then(id1.cmp(&id2)));
+
+        let num_standing = standing.len();
+        if num_standing < positions_remaining + 2 {
+            return None;
+        }
+
+        let zero = Int::zero();
+        let shortfall = |votes: &Int| {
+            if votes < quota {
+                quota - votes
+            } else {
+                zero.clone()
+            }
+        };
+
+        let leading_shortfall = shortfall(standing[num_standing - 1].1);
+        let vacancy_shortfall = standing[num_standing - positions_remaining..]
+            .iter()
+            .fold(Int::zero(), |acc, &(_, votes)| acc + shortfall(votes));
+
+        // Highest standing candidates meeting each shortfall condition.
+        let mut candidate_a = None;
+        let mut candidate_b = None;
+        let mut notional_vote = Int::zero();
+
+        // Excluding the candidate at index `i` and those below leaves `num_standing - i - 1`.
+        for i in 0..num_standing - positions_remaining {
+            notional_vote += standing[i].1;
+
+            if notional_vote >= *standing[i + 1].1 {
+                continue;
+            }
+            if notional_vote < leading_shortfall {
+                candidate_a = Some(i);
+            }
+            if notional_vote < vacancy_shortfall {
+                candidate_b = Some(i);
+            }
+        }
+
+        // Exclude the largest legal set of candidates.
+        let (highest, rule) = match (candidate_a, candidate_b) {
+            (Some(a), Some(b)) if b > a => (b, ExclusionRule::VacancyShortfall),
+            (Some(a), _) => (a, ExclusionRule::LeadingShortfall),
+            (None, Some(b)) => (b, ExclusionRule::VacancyShortfall),
+            (None, None) => return None,
+        };
+
+        // A lone candidate is excluded by the usual rule, which takes care of ties.
+        if highest == 0 {
+            return None;
+        }
+
+        let excluded = standing[..=highest].iter().map(|&(id, _)| id).collect();
+        Some((excluded, rule))
     }
 
     pub fn print_summary(&self) {
         trace!("Vote tallies");
-        for (candidate, info) in self.info.iter().filter(|&(_, i)| !i.eliminated) {
+        for (candidate, info) in self.candidates_remaining() {
             trace!(
                 "{:?}: {:?} votes",
-                self.candidates[candidate],
+                self.candidates[&candidate],
                 info.votes.latest()
             );
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate_map(num_candidates: CandidateId) -> CandidateMap {
+        (0..num_candidates)
+            .map(|id| {
+                let candidate = Candidate {
+                    id,
+                    surname: format!("CANDIDATE{}", id),
+                    other_names: String::new(),
+                    group_name: "UG".to_string(),
+                    party: String::new(),
+                    state: "NSW".to_string(),
+                };
+                (id, candidate)
+            })
+            .collect()
+    }
+
+    /// Create single-preference ballots giving each candidate the listed number of votes.
+    fn first_preferences(votes: &[u32]) -> BallotArena {
+        let mut arena = BallotArena::new();
+        for (id, &n) in votes.iter().enumerate() {
+            for _ in 0..n {
+                unwrap!(arena.push(&[id as CandidateId], 1));
+            }
+        }
+        arena
+    }
+
+    #[test]
+    fn bulk_exclusion() {
+        let candidates = candidate_map(5);
+        let ballots = first_preferences(&[40, 30, 20, 6, 5]);
+        let mut vote_map = VoteMap::new(&candidates, &ballots, TieHistory::Backward).unwrap();
+        for paper in ballots.papers() {
+            vote_map.add(0, paper);
+        }
+        let quota = Int::from(50);
+
+        // Leading shortfall is 10, so only candidate 4 could go on that basis, but the vacancy
+        // shortfall of 30 allows candidates 3 and 4 to go together.
+        assert_eq!(
+            vote_map.get_bulk_exclusion(&quota, 2),
+            Some((vec![4, 3], ExclusionRule::VacancyShortfall))
+        );
+
+        // With a single vacancy both shortfalls are 10, which only candidate 4 falls short of.
+        assert_eq!(vote_map.get_bulk_exclusion(&quota, 1), None);
+
+        let excluded = vote_map
+            .exclude_candidates(
+                &quota,
+                2,
+                ExclusionStrategy::Bulk,
+                &mut SeededTieBreaker::new(0),
+            )
+            .unwrap();
+        let ids: Vec<_> = excluded.iter().map(|e| (e.id, e.rule)).collect();
+        assert_eq!(
+            ids,
+            vec![
+                (4, ExclusionRule::VacancyShortfall),
+                (3, ExclusionRule::VacancyShortfall)
+            ]
+        );
+        assert_eq!(vote_map.num_candidates_remaining(), 3);
+    }
+
+    #[test]
+    fn snapshot() {
+        let candidates = candidate_map(3);
+        let ballots = first_preferences(&[5, 3, 2]);
+        let mut vote_map = VoteMap::new(&candidates, &ballots, TieHistory::Backward).unwrap();
+        for paper in ballots.papers() {
+            vote_map.add(0, paper);
+        }
+
+        // Papers are handles into the arena, so a copy of the count can go its own way.
+        let before = vote_map.clone();
+        let excluded = vote_map.exclude_candidate_by_id(2, ExclusionRule::Lowest);
+        for transfer in excluded.transfers {
+            vote_map.transfer_preferences(1, transfer);
+        }
+        assert_eq!(vote_map.num_candidates_remaining(), 2);
+        assert_eq!(before.num_candidates_remaining(), 3);
+        let papers = |map: &VoteMap<Paper>| -> Vec<u32> {
+            map.tallies().into_iter().map(|(_, _, p, _)| p).collect()
+        };
+        assert_eq!(papers(&before), vec![5, 3, 2]);
+        assert_eq!(papers(&vote_map), vec![5, 3, 0]);
+    }
+
+    #[test]
+    fn sparse_candidate_ids() {
+        // Candidates for states after the first have IDs starting well above 0.
+        let candidates: CandidateMap = candidate_map(4)
+            .into_iter()
+            .map(|(id, mut c)| {
+                c.id = 300 - 10 * id;
+                (c.id, c)
+            })
+            .collect();
+        let ballots: BallotArena = [
+            Ballot::multi(3, vec![300, 270]),
+            Ballot::multi(2, vec![290]),
+            Ballot::multi(1, vec![280, 290]),
+            Ballot::multi(4, vec![270]),
+        ]
+        .iter()
+        .collect();
+        let mut vote_map = VoteMap::new(&candidates, &ballots, TieHistory::Backward).unwrap();
+        for paper in ballots.papers() {
+            vote_map.add(0, paper);
+        }
+        assert_eq!(vote_map.index_of(270), 0);
+        assert_eq!(vote_map.id_at(3), 300);
+
+        let excluded = vote_map.exclude_candidate_by_id(280, ExclusionRule::Lowest);
+        for transfer in excluded.transfers {
+            vote_map.transfer_preferences(1, transfer);
+        }
+        let tallies: Vec<_> = vote_map
+            .tallies()
+            .into_iter()
+            .map(|(id, status, papers, _)| (id, status, papers))
+            .collect();
+        assert_eq!(
+            tallies,
+            vec![
+                (270, CandidateStatus::Continuing, 4),
+                (280, CandidateStatus::Excluded, 0),
+                (290, CandidateStatus::Continuing, 3),
+                (300, CandidateStatus::Continuing, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn single_exclusion_without_bulk() {
+        let candidates = candidate_map(5);
+        let ballots = first_preferences(&[40, 30, 20, 6, 5]);
+        let mut vote_map = VoteMap::new(&candidates, &ballots, TieHistory::Backward).unwrap();
+        for paper in ballots.papers() {
+            vote_map.add(0, paper);
+        }
+
+        let excluded = vote_map
+            .exclude_candidates(
+                &Int::from(50),
+                2,
+                ExclusionStrategy::Single,
+                &mut SeededTieBreaker::new(0),
+            )
+            .unwrap();
+        assert_eq!(excluded.len(), 1);
+        assert_eq!(excluded[0].id, 4);
+        assert_eq!(excluded[0].rule, ExclusionRule::Lowest);
+    }
+}
@@ -1,20 +1,103 @@
 use std::collections::VecDeque;
 use std::error::Error;
+use std::rc::Rc;
 
+use ballot::{Ballot, BallotArena};
 use ballot_parse::*;
+use ballot_trie::BallotTrie;
 use candidate::*;
+use countback::{self, CasualVacancy};
+use irv;
+use meek::{self, MeekOptions};
+use rules::*;
 use senate_result::*;
+use stats::Stats;
+use tie_break::*;
+use transcript::*;
 use util::*;
 use vote_map::*;
 
-pub fn compute_quota(num_votes: u32, num_positions: usize) -> Int {
-    frac!(num_votes, num_positions as u64 + 1).ceil()
+/// Maximum number of branches to explore when checking every way of breaking ties.
+pub const MAX_TIE_BRANCHES: usize = 1024;
+
+/// Options controlling how the count is conducted.
+#[derive(Debug, Clone)]
+pub struct CountOptions {
+    /// Rules of the counting method, by default those of the 2016 Senate count.
+    pub rules: Rc<dyn CountingRules>,
+    /// Quota formula to use instead of the one given by the rules.
+    pub quota: Option<QuotaFormula>,
+    /// Count by Meek's method instead, ignoring the rules, the quota and the options below.
+    pub meek: Option<MeekOptions>,
+    /// Fill a single vacancy by instant-runoff voting instead, ignoring the rules, the quota and
+    /// the options below.
+    pub irv: bool,
+    /// Rather than breaking unresolved ties at random, follow the count down every possible
+    /// choice and check that the outcome is the same for all of them.
+    pub explore_ties: bool,
+    /// How to break ties otherwise.
+    pub tie_break: TieBreakMethod,
+    /// How to break ties using votes at earlier counts.
+    pub tie_history: TieHistory,
+    /// After a double dissolution, work out which senators get six-year terms, both by order of
+    /// election and by recounting the ballots for half the vacancies (s282).
+    pub allocate_terms: bool,
+    /// Repeat the count until it has been run this many times in all, and report how often each
+    /// candidate was elected. Useful with rules that draw papers at random, which carry on drawing
+    /// from one count to the next, or with seeded tie breaking, which is reseeded for each count.
+    pub monte_carlo_runs: Option<usize>,
+    /// Fill a casual vacancy by a countback of the vacating member's quota of papers, after the
+    /// count. A countback can also be conducted later from the result, with
+    /// `countback::fill_vacancy`.
+    pub countback: Option<CasualVacancy>,
+    /// Hold the ballots in a trie of their preferences, and transfer them a branch at a time,
+    /// which counts in about the same time as holding them one by one. Only works with rules that
+    /// transfer surpluses in whole parcels, and not with a countback.
+    pub ballot_trie: bool,
+    /// Number of threads to parse a preferences file on, a chunk of rows at a time.
+    pub parse_threads: usize,
+}
+
+impl Default for CountOptions {
+    fn default() -> CountOptions {
+        CountOptions {
+            rules: Rc::new(Senate2016::default()),
+            quota: None,
+            meek: None,
+            irv: false,
+            explore_ties: false,
+            tie_break: TieBreakMethod::default(),
+            tie_history: TieHistory::default(),
+            allocate_terms: false,
+            monte_carlo_runs: None,
+            countback: None,
+            ballot_trie: false,
+            parse_threads: 1,
+        }
+    }
+}
+
+/// The formal ballots, held one by one or in a trie.
+pub enum Ballots {
+    Papers(BallotArena),
+    Trie(BallotTrie),
+}
+
+impl Ballots {
+    /// Hold the ballots one by one, or in a trie if the options ask for one.
+    pub fn new(ballots: BallotArena, options: &CountOptions) -> Self {
+        if options.ballot_trie {
+            Ballots::Trie(BallotTrie::from(&ballots))
+        } else {
+            Ballots::Papers(ballots)
+        }
+    }
 }
 
-fn elect_candidates<'a>(
-    elected: Vec<CandidateElected<'a>>,
+fn elect_candidates<P>(
+    elected: Vec<CandidateElected<P>>,
     result: &mut Senate,
-    preference_transfers: &mut VecDeque<PreferenceTransfer<'a>>,
+    preference_transfers: &mut VecDeque<Vec<PreferenceTransfer<P>>>,
     candidates: &CandidateMap,
 ) {
     for c in elected {
@@ -23,125 +106,850 @@ fn elect_candidates<'a>(
             candidates[&c.id], c.votes
         ));
         result.add_senator(c.id, c.votes, candidates);
-        preference_transfers.extend(c.transfers);
+        if let Some(papers) = c.quota_papers {
+            result.quota_papers.insert(c.id, papers);
+        }
+        preference_transfers.extend(c.transfers.into_iter().map(|t| vec![t]));
     }
 }
 
-fn exclude_candidates<'a, 'b: 'a>(
-    excluded: Vec<CandidateExcluded<'a>>,
-    preference_transfers: &mut VecDeque<PreferenceTransfer<'a>>,
-    candidates: &'b CandidateMap,
+fn exclude_candidates<P>(
+    excluded: Vec<CandidateExcluded<P>>,
+    preference_transfers: &mut VecDeque<Vec<PreferenceTransfer<P>>>,
+    candidates: &CandidateMap,
 ) {
-    for CandidateExcluded { id, transfers } in excluded {
-        CANDIDATE_ORDER.write(format!("Excluded {:?}", candidates[&id]));
-        preference_transfers.extend(transfers);
+    // Bulk excluded candidates have the papers they hold at each transfer value distributed
+    // together at one count, in order of decreasing transfer value.
+    let mut by_value: BTreeMap<Frac, Vec<PreferenceTransfer<P>>> = BTreeMap::new();
+    for CandidateExcluded {
+        id,
+        rule,
+        transfers,
+    } in excluded
+    {
+        CANDIDATE_ORDER.write(format!("Excluded {:?} ({:?})", candidates[&id], rule));
+        for transfer in transfers {
+            by_value
+                .entry(transfer.1.clone())
+                .or_default()
+                .push(transfer);
+        }
     }
+    preference_transfers.extend(by_value.into_iter().rev().map(|(_, transfers)| transfers));
 }
 
-pub fn decide_election<I>(
+/// If two continuing candidates remain for the last vacancy, elect the one with more votes as
+/// required by section 273(17), and return `true`.
+fn fill_last_vacancy<P: Papers>(
+    vote_map: &mut VoteMap<P>,
+    rules: &dyn CountingRules,
+    positions_remaining: usize,
+    count: usize,
+    result: &mut Senate,
     candidates: &CandidateMap,
-    disqualified_candidates: &[CandidateId],
-    ballot_stream: I,
-    num_positions: usize,
-) -> Result<Senate, Box<Error>>
-where
-    I: IntoIterator<Item = IOBallot>,
-{
-    let mut result = Senate::new();
+    tie_breaker: &mut dyn TieBreaker,
+) -> Result<bool, String> {
+    if !rules.last_two_candidates()
+        || positions_remaining != 1
+        || vote_map.num_candidates_remaining() != 2
+    {
+        return Ok(false);
+    }
+    let elected = vote_map.elect_highest(tie_breaker)?;
+    record_ties(vote_map, count, result, candidates);
+    CANDIDATE_ORDER.write(format!(
+        "Elected {:?} with {:?} votes (last two candidates)",
+        candidates[&elected.id], elected.votes
+    ));
+    result.add_senator(elected.id, elected.votes, candidates);
+    Ok(true)
+}
 
-    // Ingest ballots.
-    let mut ballots = vec![];
+/// Add the papers and votes exhausted by a transfer, and its loss by fraction, to the progressive
+/// totals.
+fn add_outcome(
+    outcome: TransferOutcome,
+    exhausted: &mut (u32, Int, Frac),
+    loss_by_fraction: &mut Int,
+) {
+    exhausted.0 += outcome.exhausted_papers;
+    exhausted.1 += outcome.exhausted_votes;
+    exhausted.2 += outcome.exhausted_value;
+    *loss_by_fraction += outcome.loss_by_fraction;
+}
 
-    for maybe_ballot in ballot_stream {
+/// Move ties recorded by the vote map at the given count into the result.
+fn record_ties<P: Papers>(
+    vote_map: &mut VoteMap<P>,
+    count: usize,
+    result: &mut Senate,
+    candidates: &CandidateMap,
+) {
+    for mut tie in vote_map.take_ties() {
+        tie.count = count;
+        result.record_tie(tie, candidates);
+    }
+}
+
+/// Formal ballots gathered for a count, with the stats of every ballot read.
+///
+/// Ballots with identical preferences are merged into one weighted ballot unless the rules need
+/// every paper separately.
+pub struct Ingest {
+    ballots: BallotArena,
+    distinct: HashMap<Vec<CandidateId>, usize>,
+    merge: bool,
+    stats: Stats,
+}
+
+impl Ingest {
+    pub fn new(options: &CountOptions) -> Self {
+        Ingest {
+            ballots: BallotArena::new(),
+            distinct: HashMap::new(),
+            // Meek's method and instant-runoff voting look at every paper at every round, so they
+            // always merge them.
+            merge: options.meek.is_some() || options.irv || options.rules.merge_identical_ballots(),
+            stats: Stats::new(),
+        }
+    }
+
+    /// Add a formal ballot or record an informal one, failing on an error reading the input.
+    pub fn add(&mut self, maybe_ballot: IOBallot) -> Result<(), Box<dyn Error>> {
         match maybe_ballot {
             Ok(ballot) => {
-                result.stats.record_valid_vote(&ballot);
-                ballots.push(ballot);
+                self.stats.record_valid_vote(&ballot);
+                self.push(ballot)?;
             }
             Err(InvalidBallot(err)) => {
                 // TODO: make ballot parsing errors a hard failure.
-                result.stats.record_invalid_vote(err);
+                self.stats.record_invalid_vote(err);
             }
             Err(InputError(e)) => {
                 return Err(e);
             }
         };
+        Ok(())
+    }
+
+    /// Add formal ballots parsed elsewhere, along with the stats recorded while parsing them.
+    pub fn add_parsed(&mut self, ballots: Vec<Ballot>, stats: Stats) -> Result<(), String> {
+        self.stats.merge(stats);
+        for ballot in ballots {
+            self.push(ballot)?;
+        }
+        Ok(())
+    }
+
+    /// The ballots gathered, held the way the options ask, and the stats of every ballot read.
+    pub fn into_ballots(self, options: &CountOptions) -> (Ballots, Stats) {
+        (Ballots::new(self.ballots, options), self.stats)
     }
 
-    // Map from candidate IDs to numbers of votes.
-    let mut vote_map = VoteMap::new(candidates)?;
+    fn push(&mut self, ballot: Ballot) -> Result<(), String> {
+        if !self.merge {
+            self.ballots.push(&ballot.prefs, ballot.weight())?;
+            return Ok(());
+        }
+        match self.distinct.get(&ballot.prefs) {
+            Some(&idx) => self.ballots.add_weight(idx, ballot.weight()),
+            None => {
+                let idx = self.ballots.push(&ballot.prefs, ballot.weight())?;
+                self.distinct.insert(ballot.prefs, idx);
+            }
+        }
+        Ok(())
+    }
+}
 
-    // Allocate first preference votes.
-    for ballot_ref in &mut ballots {
-        vote_map.add(0, ballot_ref);
+pub fn decide_election<I>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballot_stream: I,
+    num_positions: usize,
+    options: &CountOptions,
+) -> Result<Senate, Box<dyn Error>>
+where
+    I: IntoIterator<Item = IOBallot>,
+{
+    let mut ingest = Ingest::new(options);
+    for maybe_ballot in ballot_stream {
+        ingest.add(maybe_ballot)?;
     }
+    count_ingested(
+        candidates,
+        disqualified_candidates,
+        ingest,
+        num_positions,
+        options,
+    )
+}
 
-    let quota = compute_quota(result.stats.num_valid_votes(), num_positions);
+/// Count ballots already held the way the count reads them, starting afresh from their first
+/// preferences, so that the same ballots can be counted again and again.
+///
+/// This is the count alone, by the rules and the quota of the options: it doesn't explore ties,
+/// conduct a special count, allocate terms, fill a casual vacancy or repeat the count.
+pub fn recount(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: &Ballots,
+    stats: &Stats,
+    num_positions: usize,
+    options: &CountOptions,
+) -> Result<Senate, Box<dyn Error>> {
+    count(
+        candidates,
+        disqualified_candidates,
+        ballots,
+        stats.clone(),
+        num_positions,
+        options,
+        &mut *options.tie_break.tie_breaker(),
+    )
+}
 
-    let mut preference_transfers = VecDeque::new();
+/// Compute the election outcome from the ballots already ingested.
+pub fn count_ingested(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ingest: Ingest,
+    num_positions: usize,
+    options: &CountOptions,
+) -> Result<Senate, Box<dyn Error>> {
+    let Ingest { ballots, stats, .. } = ingest;
 
-    // Exclude all the disqualified candidates.
-    for &disqual_id in disqualified_candidates {
-        let CandidateExcluded { id, transfers } = vote_map.exclude_candidate_by_id(disqual_id);
-        CANDIDATE_ORDER.write(format!("Disqualified {:?}", candidates[&id]));
-        for transfer in transfers {
-            vote_map.transfer_preferences(0, transfer, &mut result.stats);
+    if options.irv && options.meek.is_some() {
+        return Err("instant-runoff voting and Meek's method can't both be used".into());
+    }
+
+    if let Some(ref meek_options) = options.meek {
+        return meek::count(
+            candidates,
+            disqualified_candidates,
+            &ballots,
+            stats,
+            num_positions,
+            meek_options,
+            &mut *options.tie_break.tie_breaker(),
+        );
+    }
+
+    if options.irv {
+        if num_positions != 1 {
+            return Err(format!(
+                "instant-runoff voting fills a single vacancy, not {}",
+                num_positions
+            )
+            .into());
         }
+        return irv::count(
+            candidates,
+            disqualified_candidates,
+            &ballots,
+            stats,
+            &mut *options.tie_break.tie_breaker(),
+        );
     }
 
-    info!("Count #1");
-    let elected_on_first_prefs = vote_map.elect_candidates_with_quota(&quota);
-    elect_candidates(
-        elected_on_first_prefs,
-        &mut result,
-        &mut preference_transfers,
+    let ballots = Ballots::new(ballots, options);
+
+    let mut result = if options.explore_ties {
+        explore_ties(
+            candidates,
+            disqualified_candidates,
+            &ballots,
+            &stats,
+            num_positions,
+            options,
+        )?
+    } else {
+        count(
+            candidates,
+            disqualified_candidates,
+            &ballots,
+            stats.clone(),
+            num_positions,
+            options,
+            &mut *options.tie_break.tie_breaker(),
+        )?
+    };
+
+    if let Some(ref vacancy) = options.countback {
+        result.countback = Some(countback::fill_vacancy(
+            candidates,
+            &ballots,
+            &result,
+            vacancy,
+            &mut *options.tie_break.tie_breaker(),
+        )?);
+    }
+
+    if let Some(runs) = options.monte_carlo_runs {
+        result.monte_carlo = Some(monte_carlo(
+            candidates,
+            disqualified_candidates,
+            &ballots,
+            &stats,
+            &result,
+            runs,
+            options,
+        )?);
+    }
+
+    // Compare a special count to the count without any candidates disqualified.
+    if !disqualified_candidates.is_empty() {
+        CANDIDATE_ORDER.write("Original count, without disqualifications");
+        let original = count(
+            candidates,
+            &[],
+            &ballots,
+            stats.clone(),
+            num_positions,
+            options,
+            &mut *options.tie_break.tie_breaker(),
+        )?;
+        result.original_senators = Some(original.senators.into_iter().map(|(c, _)| c).collect());
+    }
+
+    if options.allocate_terms {
+        result.terms = Some(allocate_terms(
+            candidates,
+            disqualified_candidates,
+            &ballots,
+            &stats,
+            &result,
+            options,
+        )?);
+    }
+
+    Ok(result)
+}
+
+/// Rerun the count on the same ballots, and tally how many times each candidate is elected over
+/// `runs` counts, including the one already conducted.
+fn monte_carlo(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: &Ballots,
+    stats: &Stats,
+    result: &Senate,
+    runs: usize,
+    options: &CountOptions,
+) -> Result<ElectionFrequency, Box<dyn Error>> {
+    let mut times_elected: BTreeMap<CandidateId, usize> =
+        candidates.keys().map(|&id| (id, 0)).collect();
+    for (c, _) in &result.senators {
+        *unwrap!(times_elected.get_mut(&c.id)) += 1;
+    }
+
+    for run in 2..=runs {
+        CANDIDATE_ORDER.write(format!("Monte Carlo count {} of {}", run, runs));
+        let rerun = count(
+            candidates,
+            disqualified_candidates,
+            ballots,
+            stats.clone(),
+            result.num_elected(),
+            options,
+            &mut *options.tie_break.tie_breaker_for_run(run - 1),
+        )?;
+        for (c, _) in rerun.senators {
+            *unwrap!(times_elected.get_mut(&c.id)) += 1;
+        }
+    }
+
+    let mut times_elected: Vec<_> = times_elected
+        .into_iter()
+        .map(|(id, times)| (candidates[&id].clone(), times))
+        .collect();
+    times_elected.sort_by(|(c1, t1), (c2, t2)| t2.cmp(t1).then(c1.id.cmp(&c2.id)));
+
+    Ok(ElectionFrequency {
+        runs: runs.max(1),
+        times_elected,
+    })
+}
+
+/// Allocate six-year terms to half of the senators, by order of election and by the s282 recount.
+///
+/// The recount is conducted on the same ballots as if only half the vacancies were to be filled,
+/// with the candidates who weren't elected at the original count excluded from the start. Ties in
+/// the recount are broken afresh by the configured method.
+fn allocate_terms(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: &Ballots,
+    stats: &Stats,
+    result: &Senate,
+    options: &CountOptions,
+) -> Result<TermAllocation, Box<dyn Error>> {
+    let num_long_terms = result.senators.len() / 2;
+
+    let mut not_elected: Vec<CandidateId> = candidates
+        .keys()
+        .filter(|id| result.senators.iter().all(|(c, _)| c.id != **id))
+        .cloned()
+        .collect();
+    not_elected.sort();
+    for id in disqualified_candidates {
+        if !not_elected.contains(id) {
+            not_elected.push(*id);
+        }
+    }
+
+    CANDIDATE_ORDER.write(format!(
+        "Section 282 recount for {} vacancies",
+        num_long_terms
+    ));
+    let recount = count(
         candidates,
-    );
+        &not_elected,
+        ballots,
+        stats.clone(),
+        num_long_terms,
+        options,
+        &mut *options.tie_break.tie_breaker(),
+    )?;
+
+    Ok(TermAllocation {
+        order_of_election: result.senators[..num_long_terms]
+            .iter()
+            .map(|(c, _)| c.clone())
+            .collect(),
+        recount: recount.senators.into_iter().map(|(c, _)| c).collect(),
+    })
+}
 
-    for i in 2.. {
+/// Follow the count down each way of resolving the ties that arise, in depth-first order.
+///
+/// The result is that of the first branch, with `tied` set if any branch elects a different
+/// set of senators, in which case the outcome of every branch is listed. If there are more than
+/// `MAX_TIE_BRANCHES` branches, only the outcomes of those followed are listed, and
+/// `tie_outcomes_incomplete` is set.
+fn explore_ties(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: &Ballots,
+    stats: &Stats,
+    num_positions: usize,
+    options: &CountOptions,
+) -> Result<Senate, Box<dyn Error>> {
+    let start = match *ballots {
+        Ballots::Papers(ref arena) => explore_tie_branches(
+            candidates,
+            disqualified_candidates,
+            first_preferences(candidates, arena, arena.papers(), options)?,
+            stats,
+            num_positions,
+            options,
+        ),
+        Ballots::Trie(ref trie) => explore_tie_branches(
+            candidates,
+            disqualified_candidates,
+            first_preferences(candidates, trie, trie.first_preferences(), options)?,
+            stats,
+            num_positions,
+            options,
+        ),
+    };
+    let (mut result, outcomes, incomplete) = start?;
+
+    let first_outcome = outcomes[0].senator_ids();
+    result.tied = outcomes.iter().any(|o| o.senator_ids() != first_outcome);
+    result.tie_outcomes_incomplete = incomplete;
+    if result.tied || incomplete {
+        result.tie_outcomes = outcomes;
+    }
+    Ok(result)
+}
+
+/// Count papers held either way down each branch of the ties, returning the result of the first
+/// branch, the outcome of every branch followed, and whether any were left unexplored.
+///
+/// Each branch is counted from a copy of the state after the first preferences, with the choices
+/// of the branch it forks from made again at the ties before the fork. Copying the state only once
+/// per branch, rather than before every count in case a tie arises, keeps each branch as cheap as
+/// an ordinary count.
+fn explore_tie_branches<P: Papers>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    vote_map: VoteMap<P>,
+    stats: &Stats,
+    num_positions: usize,
+    options: &CountOptions,
+) -> Result<(Senate, Vec<TieOutcome>, bool), Box<dyn Error>> {
+    let start = CountState::new(
+        candidates,
+        disqualified_candidates,
+        vote_map,
+        stats.clone(),
+        num_positions,
+        options,
+    )?;
+
+    let mut first_result: Option<Senate> = None;
+    let mut outcomes = vec![];
+    let mut incomplete = false;
+    // The choice to make at each tie of the next branch, the ties beyond them going to the first
+    // candidate.
+    let mut next_path = Some(vec![]);
+
+    while let Some(path) = next_path {
+        if outcomes.len() == MAX_TIE_BRANCHES {
+            incomplete = true;
+            break;
+        }
+
+        let mut state = start.clone();
+        let mut tie_breaker = ForkTieBreaker::new(path);
+        while !state.next_count(
+            candidates,
+            disqualified_candidates,
+            options,
+            &mut tie_breaker,
+        )? {}
+        next_path = tie_breaker.next_path();
+
+        let result = state.finish();
+        outcomes.push(TieOutcome {
+            choices: tie_breaker.choices().to_vec(),
+            senators: result.senators.iter().map(|(c, _)| c.clone()).collect(),
+        });
+        if first_result.is_none() {
+            first_result = Some(result);
+        }
+    }
+
+    Ok((unwrap!(first_result), outcomes, incomplete))
+}
+
+/// Count the formal ballots from their first preferences.
+fn count(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    ballots: &Ballots,
+    stats: Stats,
+    num_positions: usize,
+    options: &CountOptions,
+    tie_breaker: &mut dyn TieBreaker,
+) -> Result<Senate, Box<dyn Error>> {
+    match *ballots {
+        Ballots::Papers(ref arena) => count_papers(
+            candidates,
+            disqualified_candidates,
+            first_preferences(candidates, arena, arena.papers(), options)?,
+            stats,
+            num_positions,
+            options,
+            tie_breaker,
+        ),
+        Ballots::Trie(ref trie) => count_papers(
+            candidates,
+            disqualified_candidates,
+            first_preferences(candidates, trie, trie.first_preferences(), options)?,
+            stats,
+            num_positions,
+            options,
+            tie_breaker,
+        ),
+    }
+}
+
+/// Map from candidate IDs to numbers of votes, with the papers allocated to their first
+/// preferences.
+fn first_preferences<'a, P, I>(
+    candidates: &'a CandidateMap,
+    store: &'a P::Store,
+    papers: I,
+    options: &CountOptions,
+) -> Result<VoteMap<'a, P>, Box<dyn Error>>
+where
+    P: Papers,
+    I: IntoIterator<Item = P>,
+{
+    let mut vote_map = VoteMap::new(candidates, store, options.tie_history)?;
+    for papers in papers {
+        vote_map.add(0, papers);
+    }
+    Ok(vote_map)
+}
+
+/// Count papers held either way, starting from their first preferences.
+fn count_papers<P: Papers>(
+    candidates: &CandidateMap,
+    disqualified_candidates: &[CandidateId],
+    vote_map: VoteMap<P>,
+    stats: Stats,
+    num_positions: usize,
+    options: &CountOptions,
+    tie_breaker: &mut dyn TieBreaker,
+) -> Result<Senate, Box<dyn Error>> {
+    let mut state = CountState::new(
+        candidates,
+        disqualified_candidates,
+        vote_map,
+        stats,
+        num_positions,
+        options,
+    )?;
+    while !state.next_count(candidates, disqualified_candidates, options, tie_breaker)? {}
+    Ok(state.finish())
+}
+
+/// Where a count has got to between one count and the next, which can be copied to follow each
+/// way of breaking the ties from the first preferences.
+#[derive(Clone)]
+struct CountState<'a, P: Papers> {
+    vote_map: VoteMap<'a, P>,
+    result: Senate,
+    num_positions: usize,
+    quota: Frac,
+    /// Votes are whole numbers, so a fractional quota is reached when it is rounded up.
+    votes_needed: Int,
+    /// Transfers yet to be made, each of one or more parcels at the same transfer value.
+    preference_transfers: VecDeque<Vec<PreferenceTransfer<P>>>,
+    /// Progressive totals of exhausted papers, votes and their exact value, and votes lost by
+    /// fraction.
+    exhausted: (u32, Int, Frac),
+    loss_by_fraction: Int,
+    /// The provision each candidate was excluded under, for the distribution of their papers.
+    exclusion_rules: HashMap<CandidateId, ExclusionRule>,
+    /// Number of the next count, the first preferences being count 1.
+    count: usize,
+}
+
+impl<'a, P: Papers> CountState<'a, P> {
+    /// Set up the count, with the papers of any disqualified candidates already distributed.
+    fn new(
+        candidates: &CandidateMap,
+        disqualified_candidates: &[CandidateId],
+        mut vote_map: VoteMap<'a, P>,
+        stats: Stats,
+        num_positions: usize,
+        options: &CountOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut result = Senate::new();
+        result.stats = stats;
+
+        let quota_formula = options.quota.unwrap_or_else(|| options.rules.quota());
+        let quota = quota_formula.value(result.stats.num_valid_votes(), num_positions);
+        let votes_needed = quota.ceil();
+        result.quota = quota.clone();
+        result.quota_formula = quota_formula;
+        result.transcript = Transcript::new(result.stats.num_valid_votes());
+
+        let mut exhausted = (0, Int::zero(), Frac::zero());
+        let mut loss_by_fraction = Int::zero();
+
+        // Exclude all the disqualified candidates.
+        for &disqual_id in disqualified_candidates {
+            let CandidateExcluded { id, transfers, .. } =
+                vote_map.exclude_candidate_by_id(disqual_id, ExclusionRule::Disqualified);
+            CANDIDATE_ORDER.write(format!("Disqualified {:?}", candidates[&id]));
+            for transfer in transfers {
+                let outcome = vote_map.transfer_preferences(0, transfer);
+                add_outcome(outcome, &mut exhausted, &mut loss_by_fraction);
+            }
+        }
+
+        Ok(CountState {
+            vote_map,
+            result,
+            num_positions,
+            quota,
+            votes_needed,
+            preference_transfers: VecDeque::new(),
+            exhausted,
+            loss_by_fraction,
+            exclusion_rules: HashMap::new(),
+            count: 1,
+        })
+    }
+
+    /// Conduct the next count, returning whether every position has been filled.
+    fn next_count(
+        &mut self,
+        candidates: &CandidateMap,
+        disqualified_candidates: &[CandidateId],
+        options: &CountOptions,
+        tie_breaker: &mut dyn TieBreaker,
+    ) -> Result<bool, Box<dyn Error>> {
+        let rules = &*options.rules;
+        let i = self.count;
+        self.count += 1;
         info!("Count #{}", i);
 
+        let result = &mut self.result;
+        let vote_map = &mut self.vote_map;
+        let preference_transfers = &mut self.preference_transfers;
+
+        if i == 1 {
+            let elected_on_first_prefs = vote_map.elect_candidates_with_quota(
+                &self.quota,
+                self.num_positions,
+                rules,
+                tie_breaker,
+            )?;
+            record_ties(vote_map, 1, result, candidates);
+            elect_candidates(
+                elected_on_first_prefs,
+                result,
+                preference_transfers,
+                candidates,
+            );
+            let elected = result.senators.iter().map(|(c, _)| c.id).collect();
+            result.transcript.record_count(
+                CountAction::FirstPreferences,
+                disqualified_candidates.to_vec(),
+                elected,
+                vote_map.tallies(),
+                self.exhausted.clone(),
+                self.loss_by_fraction.clone(),
+            )?;
+            return Ok(false);
+        }
+
+        let positions_remaining = self.num_positions - result.num_elected();
+        if positions_remaining == 0 {
+            return Ok(true);
+        }
+
+        let mut excluded_ids = vec![];
+
         if preference_transfers.is_empty() {
             // If the number of candidates remaining is equal to the number of positions, elect
             // them all.
-            let positions_remaining = num_positions - result.num_elected();
-            if vote_map.num_candidates_remaining() == positions_remaining {
+            if rules.elect_remaining() && vote_map.num_candidates_remaining() == positions_remaining
+            {
                 let remaining = vote_map.elect_remaining();
-                elect_candidates(
-                    remaining,
-                    &mut result,
-                    &mut preference_transfers,
-                    candidates,
-                );
-                break;
+                for c in &remaining {
+                    result.transcript.elect_at_last_count(c.id);
+                }
+                elect_candidates(remaining, result, preference_transfers, candidates);
+                return Ok(true);
+            }
+
+            if fill_last_vacancy(
+                vote_map,
+                rules,
+                positions_remaining,
+                i,
+                result,
+                candidates,
+                tie_breaker,
+            )? {
+                let id = unwrap!(result.senators.last()).0.id;
+                result.transcript.elect_at_last_count(id);
+                return Ok(true);
             }
 
             // Exclude some candidates if we've run out of things to do.
             // as per section 13 of COMMONWEALTH ELECTORAL ACT 1918 - SECT 273
-            let excluded = vote_map.exclude_candidates();
-            exclude_candidates(excluded, &mut preference_transfers, candidates);
+            let excluded = vote_map.exclude_candidates(
+                &self.votes_needed,
+                positions_remaining,
+                rules.exclusion(),
+                tie_breaker,
+            )?;
+            record_ties(vote_map, i, result, candidates);
+            excluded_ids = excluded.iter().map(|e| e.id).collect();
+            self.exclusion_rules
+                .extend(excluded.iter().map(|e| (e.id, e.rule)));
+            exclude_candidates(excluded, preference_transfers, candidates);
+
+            // The excluded candidates' papers aren't distributed if it leaves two candidates for
+            // the last vacancy.
+            if fill_last_vacancy(
+                vote_map,
+                rules,
+                positions_remaining,
+                i,
+                result,
+                candidates,
+                tie_breaker,
+            )? {
+                let id = unwrap!(result.senators.last()).0.id;
+                result.transcript.record_count(
+                    CountAction::LastVacancy,
+                    excluded_ids,
+                    vec![id],
+                    vote_map.tallies(),
+                    self.exhausted.clone(),
+                    self.loss_by_fraction.clone(),
+                )?;
+                return Ok(true);
+            }
         }
 
         // Transfer pending preferences.
-        let transfer = preference_transfers
+        let transfers = preference_transfers
             .pop_front()
             .expect("election should terminate before running out of preferences to transfer");
+        let from = transfers[0].0;
+        let transfer_value = transfers[0].1.clone();
 
         trace!(
             "Transferring preferences for {:?} at value {:?}",
-            candidates[&transfer.0],
-            transfer.1
+            transfers
+                .iter()
+                .map(|t| &candidates[&t.0])
+                .collect::<Vec<_>>(),
+            transfer_value
         );
-        vote_map.transfer_preferences(i - 1, transfer, &mut result.stats);
+        let action = match vote_map.status(from) {
+            CandidateStatus::Elected => CountAction::Surplus {
+                from,
+                transfer_value,
+            },
+            _ => CountAction::Exclusion {
+                from: transfers.iter().map(|t| t.0).collect(),
+                transfer_value,
+                rule: self.exclusion_rules[&from],
+            },
+        };
+        let outcome = vote_map.transfer_merged_preferences(i - 1, transfers);
+        add_outcome(outcome, &mut self.exhausted, &mut self.loss_by_fraction);
 
         // Elect any candidates with a full quota, and stage their preference transfers.
-        let elected = vote_map.elect_candidates_with_quota(&quota);
-        elect_candidates(elected, &mut result, &mut preference_transfers, candidates);
+        let num_elected = result.num_elected();
+        let elected = vote_map.elect_candidates_with_quota(
+            &self.quota,
+            positions_remaining,
+            rules,
+            tie_breaker,
+        )?;
+        record_ties(vote_map, i, result, candidates);
+        elect_candidates(elected, result, preference_transfers, candidates);
+
+        let elected_ids = result.senators[num_elected..]
+            .iter()
+            .map(|(c, _)| c.id)
+            .collect();
+        result.transcript.record_count(
+            action,
+            excluded_ids,
+            elected_ids,
+            vote_map.tallies(),
+            self.exhausted.clone(),
+            self.loss_by_fraction.clone(),
+        )?;
 
         vote_map.print_summary();
+        Ok(false)
     }
 
-    assert_eq!(result.num_elected(), num_positions);
+    /// The result of the finished count.
+    fn finish(self) -> Senate {
+        let mut result = self.result;
+        assert_eq!(result.num_elected(), self.num_positions);
 
-    Ok(result)
+        let votes_needed = self.votes_needed;
+        result.last_seat_without_quota = result
+            .senators
+            .last()
+            .is_some_and(|(_, votes)| votes < &votes_needed);
+
+        result
+    }
 }
@@ -5,6 +5,7 @@ extern crate aus_senate;
 
 use aus_senate::election2016;
 use aus_senate::util::Int;
+use aus_senate::voting::CountOptions;
 
 #[test]
 #[ignore]
@@ -15,6 +16,8 @@ fn nsw_2016_election() {
         "data/NSW.csv",
         "NSW",
         num_senators,
+        &[],
+        &CountOptions::default(),
     )
     .unwrap();
     let expected = vec![
@@ -0,0 +1,1343 @@
+//! Counts of small, made-up elections that exercise particular rules.
+
+#[macro_use]
+extern crate aus_senate;
+extern crate gmp;
+
+use aus_senate::ballot::{Ballot, BallotArena, Paper};
+use aus_senate::candidate::*;
+use aus_senate::countback::{self, CasualVacancy};
+use aus_senate::dop;
+use aus_senate::exhausted_votes;
+use aus_senate::meek::MeekOptions;
+use aus_senate::rules::*;
+use aus_senate::senate_result::Senate;
+use aus_senate::tie_break::*;
+use aus_senate::transcript::*;
+use aus_senate::util::{Frac, Int};
+use aus_senate::voting::*;
+use gmp::mpq::Mpq;
+use gmp::mpz::Mpz;
+use std::env;
+use std::rc::Rc;
+
+fn candidate_map(num_candidates: CandidateId) -> CandidateMap {
+    (0..num_candidates)
+        .map(|id| {
+            let candidate = Candidate {
+                id,
+                surname: format!("CANDIDATE{}", id),
+                other_names: String::new(),
+                group_name: "UG".to_string(),
+                party: String::new(),
+                state: "NSW".to_string(),
+            };
+            (id, candidate)
+        })
+        .collect()
+}
+
+/// Create `n` copies of a ballot with the given preferences.
+fn ballots(n: usize, prefs: &[CandidateId]) -> Vec<Ballot> {
+    (0..n).map(|_| Ballot::single(prefs.to_vec())).collect()
+}
+
+/// Count the ballots with no candidates disqualified, which must succeed.
+fn count_ballots(
+    candidates: &CandidateMap,
+    ballots: Vec<Ballot>,
+    num_positions: usize,
+    options: &CountOptions,
+) -> Senate {
+    decide_election(
+        candidates,
+        &[],
+        ballots.into_iter().map(Ok),
+        num_positions,
+        options,
+    )
+    .unwrap()
+}
+
+fn elected_ids(senators: &[(Candidate, Int)]) -> Vec<CandidateId> {
+    senators.iter().map(|(c, _)| c.id).collect()
+}
+
+/// Candidates 1 and 2 tie for exclusion at count 3, and whoever survives takes the other's votes
+/// and the second seat.
+fn divergent_tie_election() -> (CandidateMap, Vec<Ballot>) {
+    let candidates = candidate_map(5);
+    let mut all_ballots = ballots(8, &[0]);
+    all_ballots.extend(ballots(3, &[1, 2]));
+    all_ballots.extend(ballots(3, &[2, 1]));
+    all_ballots.extend(ballots(4, &[3]));
+    all_ballots.extend(ballots(5, &[4]));
+    (candidates, all_ballots)
+}
+
+#[test]
+fn explore_divergent_ties() {
+    let (candidates, all_ballots) = divergent_tie_election();
+    let options = CountOptions {
+        explore_ties: true,
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 2, &options);
+
+    assert!(result.tied);
+    assert!(!result.tie_outcomes_incomplete);
+    assert_eq!(result.tie_outcomes.len(), 2);
+    assert_eq!(result.tie_outcomes[0].choices, vec![1]);
+    assert_eq!(result.tie_outcomes[0].senator_ids(), vec![0, 2]);
+    assert_eq!(result.tie_outcomes[1].choices, vec![2]);
+    assert_eq!(result.tie_outcomes[1].senator_ids(), vec![0, 1]);
+    assert_eq!(elected_ids(&result.senators), vec![0, 2]);
+}
+
+#[test]
+fn explore_harmless_ties() {
+    // Candidates 1 and 2 tie for exclusion, but candidate 0 wins either way.
+    let candidates = candidate_map(3);
+    let mut all_ballots = ballots(3, &[0]);
+    all_ballots.extend(ballots(2, &[1, 0]));
+    all_ballots.extend(ballots(2, &[2, 0]));
+
+    let options = CountOptions {
+        explore_ties: true,
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 1, &options);
+
+    assert!(!result.tied);
+    assert!(result.tie_outcomes.is_empty());
+    assert_eq!(elected_ids(&result.senators), vec![0]);
+}
+
+#[test]
+fn explore_too_many_ties() {
+    // Eight candidates with a vote each tie at every exclusion and for the last vacancy, which
+    // gives 8! ways to break the ties.
+    let candidates = candidate_map(8);
+    let all_ballots = (0..8).flat_map(|id| ballots(1, &[id])).collect::<Vec<_>>();
+
+    let options = CountOptions {
+        explore_ties: true,
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 1, &options);
+
+    assert!(result.tied);
+    assert!(result.tie_outcomes_incomplete);
+    assert_eq!(result.tie_outcomes.len(), MAX_TIE_BRANCHES);
+    assert_eq!(result.tie_outcomes[0].choices, vec![0, 1, 2, 3, 4, 5, 6]);
+    assert_eq!(result.tie_outcomes[0].senator_ids(), vec![6]);
+    assert_eq!(result.tie_outcomes[1].choices, vec![0, 1, 2, 3, 4, 5, 7]);
+    assert_eq!(elected_ids(&result.senators), vec![6]);
+}
+
+#[test]
+fn scripted_tie_break_is_recorded() {
+    let (candidates, all_ballots) = divergent_tie_election();
+    let options = CountOptions {
+        tie_break: TieBreakMethod::Scripted(vec![1]),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 2, &options);
+
+    assert_eq!(elected_ids(&result.senators), vec![0, 1]);
+    assert_eq!(result.ties.len(), 1);
+    let tie = &result.ties[0];
+    assert_eq!(tie.count, 3);
+    assert_eq!(tie.kind, TieKind::Exclusion);
+    assert_eq!(tie.candidates, vec![1, 2]);
+    assert_eq!(tie.chosen, 2);
+    assert_eq!(tie.settled_by, TieSettlement::TieBreaker);
+}
+
+#[test]
+fn missing_tie_break_decision_is_an_error() {
+    let (candidates, all_ballots) = divergent_tie_election();
+    let options = CountOptions {
+        tie_break: TieBreakMethod::Scripted(vec![]),
+        ..CountOptions::default()
+    };
+    let result = decide_election(
+        &candidates,
+        &[],
+        all_ballots.into_iter().map(Ok),
+        2,
+        &options,
+    );
+    assert!(result.is_err());
+}
+
+/// Candidates 1 and 2 tie at count 4. Candidate 1 was behind at count 1 but ahead at count 2.
+fn look_back_election() -> (CandidateMap, Vec<Ballot>) {
+    let candidates = candidate_map(5);
+    let mut all_ballots = ballots(10, &[0]);
+    all_ballots.extend(ballots(4, &[1]));
+    all_ballots.extend(ballots(5, &[2]));
+    all_ballots.extend(ballots(2, &[3, 1]));
+    all_ballots.extend(ballots(1, &[4, 2]));
+    all_ballots.extend(ballots(2, &[4]));
+    (candidates, all_ballots)
+}
+
+#[test]
+fn tie_broken_on_most_recent_unequal_count() {
+    let (candidates, all_ballots) = look_back_election();
+    let result = count_ballots(&candidates, all_ballots, 1, &CountOptions::default());
+
+    let tie = &result.ties[0];
+    assert_eq!(tie.count, 4);
+    assert_eq!(tie.candidates, vec![1, 2]);
+    assert_eq!(tie.chosen, 2);
+    assert_eq!(tie.settled_by, TieSettlement::EarlierCount(2));
+}
+
+#[test]
+fn tie_broken_on_first_unequal_count() {
+    let (candidates, all_ballots) = look_back_election();
+    let options = CountOptions {
+        tie_history: TieHistory::Forward,
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 1, &options);
+
+    let tie = &result.ties[0];
+    assert_eq!(tie.count, 4);
+    assert_eq!(tie.chosen, 1);
+    assert_eq!(tie.settled_by, TieSettlement::EarlierCount(1));
+}
+
+#[test]
+fn three_way_tie_drawn_from_all() {
+    // Candidate 0's surplus brings candidate 3 level with candidates 1 and 2 at count 3. No
+    // earlier count has all three unequal, so any of them can be drawn for exclusion. Candidates 1
+    // and 2 are left tied after that, and are drawn between again.
+    let candidates = candidate_map(5);
+    let mut all_ballots = ballots(7, &[0, 3]);
+    all_ballots.extend(ballots(2, &[1]));
+    all_ballots.extend(ballots(2, &[2]));
+    all_ballots.extend(ballots(1, &[3]));
+    all_ballots.extend(ballots(5, &[4]));
+    let options = CountOptions {
+        tie_break: TieBreakMethod::Scripted(vec![2, 0]),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 2, &options);
+
+    let tie = &result.ties[0];
+    assert_eq!(tie.count, 3);
+    assert_eq!(tie.kind, TieKind::Exclusion);
+    assert_eq!(tie.candidates, vec![1, 2, 3]);
+    assert_eq!(tie.chosen, 3);
+    assert_eq!(tie.settled_by, TieSettlement::TieBreaker);
+    assert_eq!(result.ties[1].candidates, vec![1, 2]);
+}
+
+#[test]
+fn order_of_election_tie_drawn() {
+    // Candidates 0 and 1 reach the quota with equal votes at the first count.
+    let candidates = candidate_map(3);
+    let mut all_ballots = ballots(4, &[0]);
+    all_ballots.extend(ballots(4, &[1]));
+    all_ballots.extend(ballots(2, &[2]));
+
+    let options = CountOptions {
+        tie_break: TieBreakMethod::Scripted(vec![1]),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 2, &options);
+
+    assert_eq!(elected_ids(&result.senators), vec![1, 0]);
+    let tie = &result.ties[0];
+    assert_eq!(tie.count, 1);
+    assert_eq!(tie.kind, TieKind::OrderOfElection);
+    assert_eq!(tie.candidates, vec![0, 1]);
+    assert_eq!(tie.chosen, 1);
+    assert_eq!(tie.settled_by, TieSettlement::TieBreaker);
+}
+
+#[test]
+fn order_of_election_tie_on_earlier_count() {
+    // Candidates 0 and 1 reach the quota of 5 with equal votes at count 4, having been tied at
+    // count 3, but with candidate 0 ahead at count 2.
+    let candidates = candidate_map(5);
+    let mut all_ballots = ballots(10, &[4]);
+    all_ballots.extend(ballots(4, &[0]));
+    all_ballots.extend(ballots(3, &[1]));
+    all_ballots.extend(ballots(1, &[2, 1]));
+    all_ballots.extend(ballots(1, &[3, 0]));
+    all_ballots.extend(ballots(1, &[3, 1]));
+
+    let result = count_ballots(&candidates, all_ballots, 3, &CountOptions::default());
+
+    assert_eq!(elected_ids(&result.senators), vec![4, 0, 1]);
+    let tie = &result.ties[0];
+    assert_eq!(tie.count, 4);
+    assert_eq!(tie.kind, TieKind::OrderOfElection);
+    assert_eq!(tie.chosen, 0);
+    assert_eq!(tie.settled_by, TieSettlement::EarlierCount(2));
+}
+
+#[test]
+fn last_two_candidates() {
+    // Distributing candidate 2's papers would give candidate 1 a quota, but once candidate 2 is
+    // excluded the higher of the last two is elected straight away.
+    let candidates = candidate_map(3);
+    let mut all_ballots = ballots(4, &[0]);
+    all_ballots.extend(ballots(3, &[1]));
+    all_ballots.extend(ballots(2, &[2, 1]));
+
+    let result = count_ballots(&candidates, all_ballots, 1, &CountOptions::default());
+
+    assert_eq!(elected_ids(&result.senators), vec![0]);
+    assert_eq!(result.senators[0].1, Int::from(4));
+    assert!(result.last_seat_without_quota);
+}
+
+#[test]
+fn last_two_candidates_tied() {
+    let candidates = candidate_map(3);
+    let mut all_ballots = ballots(3, &[0]);
+    all_ballots.extend(ballots(3, &[1]));
+    all_ballots.extend(ballots(2, &[2, 1]));
+
+    let options = CountOptions {
+        tie_break: TieBreakMethod::Scripted(vec![0]),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 1, &options);
+
+    assert_eq!(elected_ids(&result.senators), vec![0]);
+    assert!(result.last_seat_without_quota);
+    let tie = &result.ties[0];
+    assert_eq!(tie.kind, TieKind::LastVacancy);
+    assert_eq!(tie.candidates, vec![0, 1]);
+    assert_eq!(tie.settled_by, TieSettlement::TieBreaker);
+}
+
+/// Candidate 0's surplus of 5 goes to candidate 1 at 5/13, and partly exhausts. Excluding
+/// candidate 1 then leaves candidates 2 and 3 for the last vacancy.
+fn surplus_election() -> (CandidateMap, Vec<Ballot>) {
+    let candidates = candidate_map(4);
+    let mut all_ballots = ballots(10, &[0, 1]);
+    all_ballots.extend(ballots(3, &[0]));
+    all_ballots.extend(ballots(4, &[2]));
+    all_ballots.extend(ballots(5, &[3]));
+    (candidates, all_ballots)
+}
+
+#[test]
+fn transcript() {
+    let (candidates, all_ballots) = surplus_election();
+    let result = count_ballots(&candidates, all_ballots, 2, &CountOptions::default());
+    let counts = &result.transcript.counts;
+    assert_eq!(counts.len(), 3);
+
+    assert_eq!(counts[0].action, CountAction::FirstPreferences);
+    assert_eq!(counts[0].elected, vec![0]);
+    assert_eq!(counts[0].candidates[0].tally.votes, Int::from(13));
+
+    assert_eq!(
+        counts[1].action,
+        CountAction::Surplus {
+            from: 0,
+            transfer_value: frac!(5u32, 13u32),
+        }
+    );
+    let surplus = &counts[1].candidates;
+    assert_eq!(surplus[0].tally.papers, 0);
+    assert_eq!(surplus[0].tally.votes, Int::from(8));
+    assert_eq!(surplus[1].tally.papers, 10);
+    assert_eq!(surplus[1].tally.votes_change, Int::from(3));
+    assert_eq!(counts[1].exhausted.papers, 3);
+    assert_eq!(counts[1].exhausted.votes, Int::from(1));
+    assert_eq!(counts[1].loss_by_fraction.votes, Int::from(1));
+
+    assert_eq!(counts[2].action, CountAction::LastVacancy);
+    assert_eq!(counts[2].excluded, vec![1]);
+    assert_eq!(counts[2].elected, vec![3]);
+    assert_eq!(counts[2].candidates[1].status, CandidateStatus::Excluded);
+}
+
+#[test]
+fn votes_conserved() {
+    let (candidates, all_ballots) = surplus_election();
+    let result = count_ballots(&candidates, all_ballots, 2, &CountOptions::default());
+    let num_votes = result.transcript.num_votes;
+    assert_eq!(num_votes, 22);
+
+    // The surplus transfer loses a vote by fraction: 10 * 5/13 and 3 * 5/13 round down to 3 and 1.
+    let mut count = result.transcript.counts[1].clone();
+    assert_eq!(count.loss_by_fraction.votes_change, Int::from(1));
+    assert_eq!(count.check_conservation(num_votes), Ok(()));
+
+    count.candidates[2].tally.votes += Int::from(1);
+    assert_eq!(
+        count.check_conservation(num_votes),
+        Err(
+            "votes not conserved at count 2: 21 for candidates + 1 exhausted + 1 lost by \
+             fraction = 23, but there are 22 formal votes"
+                .to_string()
+        )
+    );
+}
+
+#[test]
+fn exclusion_rule_recorded() {
+    let candidates = candidate_map(7);
+    let mut all_ballots = ballots(40, &[0]);
+    all_ballots.extend(ballots(15, &[1]));
+    all_ballots.extend(ballots(14, &[2]));
+    all_ballots.extend(ballots(13, &[3]));
+    all_ballots.extend(ballots(3, &[4, 2]));
+    all_ballots.extend(ballots(2, &[5, 2]));
+    all_ballots.extend(ballots(1, &[6, 1]));
+
+    for &(bulk_exclusion, rule) in &[
+        (true, ExclusionRule::LeadingShortfall),
+        (false, ExclusionRule::Lowest),
+    ] {
+        let options = CountOptions {
+            rules: Rc::new(Senate2016 { bulk_exclusion }),
+            ..CountOptions::default()
+        };
+        let result = count_ballots(&candidates, all_ballots.clone(), 2, &options);
+        let counts = &result.transcript.counts;
+
+        // Candidates 4, 5 and 6 together trail candidate 3 and the leader's shortfall of 15. In
+        // bulk, all their papers are distributed together at one count.
+        let exclusion = |from: Vec<CandidateId>| CountAction::Exclusion {
+            from,
+            transfer_value: frac!(1u32, 1u32),
+            rule,
+        };
+        if bulk_exclusion {
+            assert_eq!(counts[2].action, exclusion(vec![6, 5, 4]));
+            assert_eq!(counts[2].excluded, vec![6, 5, 4]);
+        } else {
+            let excluded: Vec<_> = counts[2..5]
+                .iter()
+                .map(|count| count.action.clone())
+                .collect();
+            assert_eq!(
+                excluded,
+                vec![exclusion(vec![6]), exclusion(vec![5]), exclusion(vec![4])]
+            );
+            assert_eq!(counts[2].excluded, vec![6]);
+        }
+        assert_eq!(elected_ids(&result.senators), vec![0, 2]);
+    }
+}
+
+#[test]
+fn bulk_exclusion_merges_parcels() {
+    // Candidate 0's surplus goes to candidates 4 and 5 at a transfer value of 1/4, and then 4, 5
+    // and 6 are excluded in bulk.
+    let candidates = candidate_map(7);
+    let mut all_ballots = ballots(28, &[0]);
+    all_ballots.extend(ballots(6, &[0, 4, 2]));
+    all_ballots.extend(ballots(6, &[0, 5, 2]));
+    all_ballots.extend(ballots(15, &[1]));
+    all_ballots.extend(ballots(14, &[2]));
+    all_ballots.extend(ballots(13, &[3]));
+    all_ballots.extend(ballots(3, &[4, 2]));
+    all_ballots.extend(ballots(2, &[5, 2]));
+    all_ballots.extend(ballots(1, &[6, 1]));
+
+    let options = CountOptions {
+        rules: Rc::new(Senate2016 {
+            bulk_exclusion: true,
+        }),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 2, &options);
+    let counts = &result.transcript.counts;
+
+    // One count for each transfer value, the highest first, taking the papers from each of the
+    // excluded candidates.
+    let exclusion = |from: Vec<CandidateId>, transfer_value: Frac| CountAction::Exclusion {
+        from,
+        transfer_value,
+        rule: ExclusionRule::LeadingShortfall,
+    };
+    assert_eq!(counts[2].excluded, vec![6, 5, 4]);
+    assert_eq!(counts[2].action, exclusion(vec![6, 5, 4], frac!(1u32)));
+    assert_eq!(counts[3].action, exclusion(vec![5, 4], frac!(1u32, 4u32)));
+    for &id in &[4, 5, 6] {
+        assert_eq!(counts[3].candidates[id].tally.papers, 0);
+        assert_eq!(counts[3].candidates[id].tally.votes, Int::zero());
+    }
+
+    // The 12 papers at 1/4 are rounded down together to 3 votes, rather than to 1 vote each for
+    // the 6 papers from candidate 4 and from candidate 5, so a vote is gained by fraction.
+    assert_eq!(counts[3].candidates[2].tally.votes_change, Int::from(3));
+    assert_eq!(counts[3].loss_by_fraction.votes_change, Int::from(-1));
+}
+
+#[test]
+fn dop_rows() {
+    let (candidates, all_ballots) = surplus_election();
+    let result = count_ballots(&candidates, all_ballots, 2, &CountOptions::default());
+    let rows = dop::rows(&result, &candidates);
+
+    // Four candidates, exhausted and gain/loss at each of three counts.
+    assert_eq!(rows.len(), 18);
+    assert!(rows
+        .iter()
+        .all(|row| row.quota == "8" && row.num_vacancies == 2));
+
+    let first = &rows[0];
+    assert_eq!((first.count, first.ballot_position), (1, 1));
+    assert_eq!(first.progressive_vote_total, "13");
+    assert_eq!(first.status, "Elected");
+    assert_eq!(first.changed, "True");
+    assert_eq!(first.order_elected, 1);
+
+    let receiving = &rows[7];
+    assert_eq!((receiving.count, receiving.ballot_position), (2, 2));
+    assert_eq!(receiving.papers, 10);
+    assert_eq!(receiving.votes_transferred, "3");
+    assert_eq!(receiving.transfer_value, "0.384615");
+    assert_eq!(rows[10].surname, dop::EXHAUSTED);
+    assert_eq!(rows[10].progressive_vote_total, "1");
+    assert_eq!(rows[11].surname, dop::GAIN_LOSS);
+    assert_eq!(rows[11].progressive_vote_total, "-1");
+
+    let excluded = &rows[13];
+    assert_eq!(
+        (excluded.status.as_str(), excluded.changed.as_str()),
+        ("Excluded", "True")
+    );
+    let last = &rows[15];
+    assert_eq!((last.status.as_str(), last.order_elected), ("Elected", 2));
+    assert_eq!(rows[12].changed, "");
+}
+
+#[test]
+fn verify_dop() {
+    let (candidates, all_ballots) = surplus_election();
+    let result = count_ballots(&candidates, all_ballots, 2, &CountOptions::default());
+    let ours = dop::rows(&result, &candidates);
+
+    let filename = env::temp_dir().join("aus_senate_verify_dop.csv");
+    let filename = filename.to_str().unwrap();
+    dop::write_out(&result, &candidates, filename).unwrap();
+    let mut theirs = dop::read_in(filename).unwrap();
+    assert_eq!(theirs, ours);
+    assert_eq!(dop::first_discrepancy(&ours, &theirs), None);
+
+    // Move a vote from candidate 2 to candidate 3 at count 2.
+    theirs[8].progressive_vote_total = "3".to_string();
+    theirs[9].progressive_vote_total = "6".to_string();
+    let discrepancy = dop::first_discrepancy(&ours, &theirs).unwrap();
+    assert_eq!(discrepancy.count, 2);
+    assert_eq!(
+        discrepancy.differences,
+        vec![
+            "CANDIDATE2: 4 votes vs 3 votes",
+            "CANDIDATE3: 5 votes vs 6 votes",
+        ]
+    );
+
+    // Tallies written differently are still the same number.
+    theirs[8].progressive_vote_total = "4.0".to_string();
+    theirs[9].progressive_vote_total = "5.00".to_string();
+    assert_eq!(dop::first_discrepancy(&ours, &theirs), None);
+
+    // Drop the last count, where candidate 1 is excluded.
+    let discrepancy = dop::first_discrepancy(&ours, &ours[..12]).unwrap();
+    assert_eq!(discrepancy.count, 3);
+    assert_eq!(discrepancy.differences, vec!["count missing from theirs"]);
+
+    // Drop the middle count, leaving the last to be compared with our own last count.
+    let mut gap = ours[..6].to_vec();
+    gap.extend_from_slice(&ours[12..]);
+    let discrepancy = dop::first_discrepancy(&ours, &gap).unwrap();
+    assert_eq!(discrepancy.count, 2);
+    assert_eq!(discrepancy.differences, vec!["count missing from theirs"]);
+    let discrepancy = dop::first_discrepancy(&gap, &ours).unwrap();
+    assert_eq!(discrepancy.count, 2);
+    assert_eq!(discrepancy.differences, vec!["count missing from ours"]);
+}
+
+#[test]
+fn long_terms_disagree() {
+    // Candidates 0, 1 and 4 reach the quota of 20 on first preferences, and 2 on 0's surplus.
+    // Recounting for two vacancies without 3 sends 3's votes to 4 rather than 1.
+    let candidates = candidate_map(5);
+    let mut all_ballots = ballots(40, &[0, 2]);
+    all_ballots.extend(ballots(21, &[1]));
+    all_ballots.extend(ballots(20, &[4]));
+    all_ballots.extend(ballots(19, &[3, 4]));
+    let options = CountOptions {
+        allocate_terms: true,
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 4, &options);
+    assert_eq!(elected_ids(&result.senators), vec![0, 1, 4, 2]);
+
+    let terms = result.terms.unwrap();
+    let ids = |senators: &[Candidate]| senators.iter().map(|c| c.id).collect::<Vec<_>>();
+    assert_eq!(ids(&terms.order_of_election), vec![0, 1]);
+    assert_eq!(ids(&terms.recount), vec![0, 4]);
+    assert!(!terms.methods_agree());
+}
+
+#[test]
+fn find_candidates_by_name() {
+    let mut candidates = candidate_map(3);
+    for &(id, given) in &[(0, "Jane Mary"), (1, "Joan"), (2, "John")] {
+        let c = candidates.get_mut(&id).unwrap();
+        c.surname = "SMITH".to_string();
+        c.other_names = given.to_string();
+    }
+    let names = |names: &[&str]| -> Vec<CandidateName> {
+        names.iter().map(|name| name.parse().unwrap()).collect()
+    };
+
+    assert_eq!(
+        find_candidates_with_names(&names(&["Smith, mary", "SMITH, John"]), &candidates),
+        Ok(vec![0, 2])
+    );
+    assert_eq!(
+        find_candidates_with_names(&names(&["SMITH, Jim"]), &candidates),
+        Err("no candidate named SMITH, Jim".to_string())
+    );
+    assert!(find_candidates_with_names(&names(&["SMITH, Jo"]), &candidates).is_err());
+    assert!("John Smith".parse::<CandidateName>().is_err());
+}
+
+#[test]
+fn special_count() {
+    // Disqualifying candidate 0 sends their votes to candidate 3, who overtakes candidate 2.
+    let candidates = candidate_map(4);
+    let mut all_ballots = ballots(5, &[0, 3]);
+    all_ballots.extend(ballots(9, &[1]));
+    all_ballots.extend(ballots(4, &[2]));
+    all_ballots.extend(ballots(2, &[3]));
+    let result = decide_election(
+        &candidates,
+        &[0],
+        all_ballots.into_iter().map(Ok),
+        2,
+        &CountOptions::default(),
+    )
+    .unwrap();
+
+    assert_eq!(elected_ids(&result.senators), vec![1, 3]);
+    assert_eq!(result.transcript.counts[0].excluded, vec![0]);
+    let ids = |senators: &[Candidate]| senators.iter().map(|c| c.id).collect::<Vec<_>>();
+    assert_eq!(ids(result.original_senators.as_ref().unwrap()), vec![1, 0]);
+
+    let (gained, lost) = result.special_count_changes().unwrap();
+    assert_eq!(gained.iter().map(|c| c.id).collect::<Vec<_>>(), vec![3]);
+    assert_eq!(lost.iter().map(|c| c.id).collect::<Vec<_>>(), vec![0]);
+}
+
+/// The 2016 rules, but without the shortcut for the last two candidates.
+#[derive(Debug)]
+struct NoLastTwo(Senate2016);
+
+impl CountingRules for NoLastTwo {
+    fn name(&self) -> String {
+        "Senate (2016) without s273(17)".to_string()
+    }
+
+    fn quota(&self) -> QuotaFormula {
+        self.0.quota()
+    }
+
+    fn plan_surplus(&self, surplus: &SurplusTally) -> Option<SurplusPlan> {
+        self.0.plan_surplus(surplus)
+    }
+
+    fn exclusion(&self) -> ExclusionStrategy {
+        self.0.exclusion()
+    }
+
+    fn last_two_candidates(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+fn custom_counting_rules() {
+    let (candidates, all_ballots) = surplus_election();
+    let options = CountOptions {
+        rules: Rc::new(NoLastTwo(Senate2016::default())),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 2, &options);
+
+    // Candidate 1's papers are distributed, then candidate 2 is excluded, leaving candidate 3.
+    let counts = &result.transcript.counts;
+    assert_eq!(counts.len(), 4);
+    assert_eq!(counts[2].excluded, vec![1]);
+    assert_eq!(counts[3].excluded, vec![2]);
+    assert_eq!(counts[3].elected, vec![3]);
+    assert_eq!(elected_ids(&result.senators), vec![0, 3]);
+}
+
+/// An arena holding the ballots of each parcel, and the parcels' papers at their values.
+fn parcels(parcels: &[(Mpq, &[Ballot])]) -> (BallotArena, TransferMap<Paper>) {
+    let arena: BallotArena = parcels.iter().flat_map(|&(_, ballots)| ballots).collect();
+    let mut papers = arena.papers();
+    let map = parcels
+        .iter()
+        .map(|(value, ballots)| {
+            let parcel = papers.by_ref().take(ballots.len()).collect();
+            (value.clone(), parcel)
+        })
+        .collect();
+    (arena, map)
+}
+
+/// Papers held by a candidate at full value and at half value, making 30 votes with a quota of 20
+/// when there are 10 and 40 of them.
+fn two_parcels(full: &[Ballot], half: &[Ballot]) -> (BallotArena, Surplus<Paper>) {
+    let (arena, papers) = parcels(&[(frac!(1u32), full), (frac!(1u32, 2u32), half)]);
+    (arena, Surplus::new(0, Int::from(30), frac!(20u32), papers))
+}
+
+fn transfer_values(transfers: Vec<PreferenceTransfer<Paper>>) -> Vec<(Mpq, usize)> {
+    transfers
+        .into_iter()
+        .map(|PreferenceTransfer(_, value, ballots)| (value, ballots.len()))
+        .collect()
+}
+
+#[test]
+fn weighted_inclusive_gregory() {
+    // 10 papers at full value and 40 at half value make 30 votes, 10 more than the quota.
+    let full = ballots(10, &[0, 1]);
+    let half = ballots(40, &[0, 2]);
+
+    // The unweighted method gives every paper the same value, 10/50.
+    let (arena, surplus) = two_parcels(&full, &half);
+    let unweighted = Senate2016::default().surplus_transfers(surplus, &arena);
+    assert_eq!(transfer_values(unweighted), vec![(frac!(1u32, 5u32), 50)]);
+
+    // The weighted method scales each parcel's value by 10/30.
+    let (arena, surplus) = two_parcels(&full, &half);
+    let exact = WeightedInclusiveGregory::default().surplus_transfers(surplus, &arena);
+    assert_eq!(
+        transfer_values(exact),
+        vec![(frac!(1u32, 3u32), 10), (frac!(1u32, 6u32), 40)]
+    );
+
+    let truncated = WeightedInclusiveGregory {
+        decimal_places: Some(2),
+        ..WeightedInclusiveGregory::default()
+    };
+    let (arena, surplus) = two_parcels(&full, &half);
+    let truncated = truncated.surplus_transfers(surplus, &arena);
+    assert_eq!(
+        transfer_values(truncated),
+        vec![(frac!(33u32, 100u32), 10), (frac!(16u32, 100u32), 40)]
+    );
+}
+
+#[test]
+fn meek() {
+    let candidates = candidate_map(3);
+    let mut all_ballots = ballots(10, &[0, 1]);
+    all_ballots.extend(ballots(4, &[1]));
+    all_ballots.extend(ballots(6, &[2]));
+    let options = CountOptions {
+        meek: Some(MeekOptions::default()),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 2, &options);
+    assert_eq!(elected_ids(&result.senators), vec![0, 1]);
+    assert_eq!(result.quota_formula, QuotaFormula::Meek);
+
+    let stages = &result.meek.unwrap().stages;
+    assert_eq!(stages.len(), 2);
+    assert!(stages.iter().all(|stage| stage.converged));
+    assert_eq!(stages[0].elected, vec![0]);
+
+    // Candidate 0 keeps two thirds of each vote (rounded up), passing a third on to candidate 1,
+    // who then exceeds the quota of 20/3.
+    let iterations = &stages[1].iterations;
+    assert_eq!(iterations.len(), 2);
+    assert_eq!(iterations[1].quota, frac!(20u32, 3u32));
+    assert_eq!(
+        iterations[1].keep_factors[0],
+        (0, frac!(666_666_667u64, 1_000_000_000u64))
+    );
+    assert_eq!(
+        iterations[1].votes[1],
+        (1, frac!(7_333_333_330u64, 1_000_000_000u64))
+    );
+    assert_eq!(stages[1].elected, vec![1]);
+}
+
+#[test]
+fn meek_exclusion_tie() {
+    // Candidate 0 has no more than the quota of 4, so candidates 1 and 2 tie for exclusion.
+    let candidates = candidate_map(3);
+    let mut all_ballots = ballots(4, &[0]);
+    all_ballots.extend(ballots(2, &[1, 0]));
+    all_ballots.extend(ballots(2, &[2, 0]));
+    let options = CountOptions {
+        meek: Some(MeekOptions::default()),
+        tie_break: TieBreakMethod::Scripted(vec![1]),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 1, &options);
+    assert_eq!(elected_ids(&result.senators), vec![0]);
+    assert_eq!(result.ties.len(), 1);
+    assert_eq!(result.ties[0].count, 1);
+    assert_eq!(result.ties[0].candidates, vec![1, 2]);
+    assert_eq!(result.ties[0].chosen, 2);
+    assert_eq!(result.meek.unwrap().stages[0].excluded, vec![2]);
+}
+
+#[test]
+fn meek_runs_out_of_candidates() {
+    // With candidate 2 disqualified, only two candidates can fill the three vacancies.
+    let candidates = candidate_map(3);
+    let mut all_ballots = ballots(10, &[0]);
+    all_ballots.extend(ballots(4, &[1]));
+    all_ballots.extend(ballots(6, &[2]));
+    let options = CountOptions {
+        meek: Some(MeekOptions::default()),
+        ..CountOptions::default()
+    };
+    let result = decide_election(
+        &candidates,
+        &[2],
+        all_ballots.into_iter().map(Ok),
+        3,
+        &options,
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn nsw_random_sample_surplus() {
+    let rules = NswRandomSample::new(0);
+    assert_eq!(rules.quota(), QuotaFormula::StatutoryDroop);
+    assert_eq!(rules.quota().value(33, 2), frac!(12u32));
+
+    // Candidate 0 has 4 papers from an earlier count, then receives a last parcel of 10 papers,
+    // one of which is exhausted, for a surplus of 4.
+    let mut papers = ballots(4, &[0, 3]);
+    papers.extend(ballots(6, &[0, 1]));
+    papers.extend(ballots(3, &[0, 2]));
+    papers.extend(ballots(1, &[0]));
+    let (arena, papers) = parcels(&[(frac!(1u32), &papers)]);
+    let mut surplus = Surplus::new(0, Int::from(14), frac!(10u32), papers);
+    surplus.last_parcel.start = 4;
+    surplus.continuing = vec![1, 2, 3].into_iter().collect();
+
+    // Shares of 24/9 and 12/9 papers round down to 2 and 1, and the paper left over goes to the
+    // larger remainder.
+    let transfers = rules.surplus_transfers(surplus, &arena);
+    assert_eq!(transfers.len(), 1);
+    let PreferenceTransfer(from, ref value, ref drawn) = transfers[0];
+    assert_eq!((from, value.clone()), (0, frac!(1u32)));
+    let next: Vec<_> = drawn.iter().map(|p| arena.prefs(p.index())[1]).collect();
+    assert_eq!(next.iter().filter(|&&c| c == 1).count(), 3);
+    assert_eq!(next.iter().filter(|&&c| c == 2).count(), 1);
+}
+
+#[test]
+fn monte_carlo() {
+    // Candidate 0's surplus of 4 goes to candidate 1, who is excluded, passing the papers drawn to
+    // candidates 2 and 3. Candidate 2 beats candidate 3 for the last vacancy only if at least 3
+    // of the 4 papers drawn prefer them.
+    let candidates = candidate_map(5);
+    let mut all_ballots = ballots(10, &[0, 1, 2]);
+    all_ballots.extend(ballots(10, &[0, 1, 3]));
+    all_ballots.extend(ballots(9, &[2]));
+    all_ballots.extend(ballots(10, &[3]));
+    all_ballots.extend(ballots(8, &[4]));
+    let options = CountOptions {
+        rules: Rc::new(NswRandomSample::new(42)),
+        monte_carlo_runs: Some(50),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 2, &options);
+    assert_eq!(result.quota, frac!(16u32));
+
+    let frequency = result.monte_carlo.unwrap();
+    assert_eq!(frequency.runs, 50);
+    let times = |id| {
+        frequency
+            .times_elected
+            .iter()
+            .find(|(c, _)| c.id == id)
+            .unwrap()
+            .1
+    };
+    assert_eq!(times(0), 50);
+    assert_eq!(frequency.proportion(0), 1.0);
+    assert_eq!(times(1) + times(4), 0);
+    assert_eq!(times(2) + times(3), 50);
+    assert!(times(2) > 0 && times(3) > times(2));
+
+    // Each run draws its own tie breaks, so the tie between candidates 1 and 2 goes both ways.
+    let (candidates, all_ballots) = divergent_tie_election();
+    let options = CountOptions {
+        monte_carlo_runs: Some(20),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 2, &options);
+    let frequency = result.monte_carlo.unwrap();
+    let times = |id| {
+        frequency
+            .times_elected
+            .iter()
+            .find(|(c, _)| c.id == id)
+            .unwrap()
+            .1
+    };
+    assert_eq!(times(1) + times(2), 20);
+    assert!(times(1) > 0 && times(2) > 0);
+}
+
+/// A surplus with 10 first preferences, and a last parcel at half value.
+fn half_value_parcel(
+    votes: u32,
+    first: &[Ballot],
+    last: &[Ballot],
+) -> (BallotArena, Surplus<Paper>) {
+    let (arena, papers) = parcels(&[(frac!(1u32), first), (frac!(1u32, 2u32), last)]);
+    let mut surplus = Surplus::new(0, Int::from(votes), frac!(12u32), papers);
+    surplus.last_parcel.transfer_value = frac!(1u32, 2u32);
+    surplus.continuing = vec![1, 2, 3].into_iter().collect();
+    (arena, surplus)
+}
+
+#[test]
+fn hare_clark_last_parcel() {
+    // Of the 10 papers in the last parcel, 2 are exhausted.
+    let first = ballots(10, &[0, 3]);
+    let mut last = ballots(8, &[0, 1]);
+    last.extend(ballots(2, &[0]));
+
+    // A surplus of 3 is transferred from the 8 unexhausted papers at 3/8.
+    let (arena, surplus) = half_value_parcel(15, &first, &last);
+    let transfers = HareClark.surplus_transfers(surplus, &arena);
+    assert_eq!(transfer_values(transfers), vec![(frac!(3u32, 8u32), 8)]);
+
+    // A surplus of 6 would give them more than the half value they were received at.
+    let (arena, surplus) = half_value_parcel(18, &first, &last);
+    let transfers = HareClark.surplus_transfers(surplus, &arena);
+    assert_eq!(transfer_values(transfers), vec![(frac!(1u32, 2u32), 8)]);
+}
+
+#[test]
+fn hare_clark() {
+    let candidates = candidate_map(5);
+    let mut all_ballots = ballots(7, &[0, 1]);
+    all_ballots.extend(ballots(3, &[4, 0, 2]));
+    all_ballots.extend(ballots(6, &[1]));
+    all_ballots.extend(ballots(6, &[2]));
+    all_ballots.extend(ballots(4, &[3]));
+    let options = CountOptions {
+        rules: Rc::new(HareClark),
+        ..CountOptions::default()
+    };
+    let result = count_ballots(&candidates, all_ballots, 2, &options);
+    assert_eq!(result.quota, frac!(9u32));
+
+    // Candidate 0 is elected on candidate 4's papers, and only those are transferred, giving
+    // candidate 2 a vote and candidate 1 nothing.
+    let counts = &result.transcript.counts;
+    assert_eq!(counts[1].excluded, vec![4]);
+    assert_eq!(counts[1].elected, vec![0]);
+    match counts[2].action {
+        CountAction::Surplus {
+            from,
+            ref transfer_value,
+        } => assert_eq!((from, transfer_value.clone()), (0, frac!(1u32, 3u32))),
+        ref action => panic!("expected a surplus, not {:?}", action),
+    }
+    let tally = |id: usize| &counts[2].candidates[id].tally;
+    assert_eq!(
+        (tally(2).papers_change, tally(2).votes_change.clone()),
+        (3, Int::from(1))
+    );
+    assert_eq!(tally(1).papers_change, 0);
+
+    // Candidates 3 and 1 are excluded in turn, with no shortcut for the last two candidates.
+    assert_eq!(counts.len(), 5);
+    assert_eq!(counts[3].excluded, vec![3]);
+    assert_eq!(counts[4].excluded, vec![1]);
+    assert_eq!(elected_ids(&result.senators), vec![0, 2]);
+}
+
+fn countback_election(vacancy: CasualVacancy) -> Result<Senate, String> {
+    let candidates = candidate_map(5);
+    let mut all_ballots = ballots(6, &[0, 1]);
+    all_ballots.extend(ballots(4, &[0, 3]));
+    all_ballots.extend(ballots(2, &[0, 4]));
+    all_ballots.extend(ballots(5, &[2]));
+    all_ballots.extend(ballots(3, &[1]));
+    all_ballots.extend(ballots(2, &[3]));
+    all_ballots.extend(ballots(1, &[4]));
+    let options = CountOptions {
+        rules: Rc::new(HareClark),
+        countback: Some(vacancy),
+        ..CountOptions::default()
+    };
+    decide_election(
+        &candidates,
+        &[],
+        all_ballots.into_iter().map(Ok),
+        2,
+        &options,
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[test]
+fn countback() {
+    let result = countback_election(CasualVacancy {
+        vacating: 0,
+        consenting: vec![1, 3, 4],
+    })
+    .unwrap();
+    assert_eq!(elected_ids(&result.senators), vec![0, 2]);
+
+    // Candidate 0's 12 papers each kept 2/3 of a vote after their surplus was transferred at 1/3.
+    let countback = result.countback.unwrap();
+    assert_eq!(countback.quota_value, frac!(8u32));
+    assert_eq!(countback.rounds.len(), 2);
+    assert_eq!(
+        countback.rounds[0].votes,
+        vec![
+            (1, frac!(4u32)),
+            (3, frac!(8u32, 3u32)),
+            (4, frac!(4u32, 3u32))
+        ]
+    );
+
+    // Nobody has more than half of the 8 votes, so candidate 4 is excluded, and their papers
+    // exhaust.
+    assert_eq!(countback.rounds[0].excluded, Some(4));
+    assert_eq!(countback.rounds[1].exhausted, frac!(4u32, 3u32));
+    assert_eq!(countback.rounds[1].excluded, None);
+    assert_eq!(countback.elected.id, 1);
+}
+
+#[test]
+fn countback_errors() {
+    let not_elected = countback_election(CasualVacancy {
+        vacating: 1,
+        consenting: vec![3, 4],
+    });
+    assert!(not_elected.unwrap_err().contains("wasn't elected"));
+
+    let elected_consenting = countback_election(CasualVacancy {
+        vacating: 0,
+        consenting: vec![2, 3],
+    });
+    assert!(elected_consenting
+        .unwrap_err()
+        .contains("can't fill a casual vacancy"));
+}
+
+#[test]
+fn countback_senate_rules() {
+    // Candidate 0's surplus reaches candidate 1 at 1/57, and the exclusions of candidates 2, 3
+    // and 4 then elect candidate 1 with a surplus of 14 from 126 papers, all transferred at 1/9.
+    let candidates = candidate_map(7);
+    let mut all_ballots = ballots(57, &[0, 1]);
+    all_ballots.extend(ballots(20, &[1]));
+    all_ballots.extend(ballots(15, &[2, 1, 5]));
+    all_ballots.extend(ballots(16, &[3, 1, 6]));
+    all_ballots.extend(ballots(18, &[4, 1, 2]));
+    all_ballots.extend(ballots(19, &[5]));
+    all_ballots.extend(ballots(21, &[6]));
+    let options = CountOptions::default();
+    let mut ingest = Ingest::new(&options);
+    for ballot in all_ballots {
+        ingest.add(Ok(ballot)).unwrap();
+    }
+    let (held, stats) = ingest.into_ballots(&options);
+    let result = recount(&candidates, &[], &held, &stats, 2, &options).unwrap();
+    assert_eq!(elected_ids(&result.senators), vec![0, 1]);
+
+    // The countback is conducted from the completed count. The papers from candidate 0 were
+    // transferred on at more than they were worth, and keep nothing rather than a negative value.
+    let vacancy = CasualVacancy {
+        vacating: 1,
+        consenting: vec![2, 3, 4, 5, 6],
+    };
+    let countback = countback::fill_vacancy(
+        &candidates,
+        &held,
+        &result,
+        &vacancy,
+        &mut ScriptedTieBreaker::new(vec![1]),
+    )
+    .unwrap();
+    let papers = match held {
+        Ballots::Papers(ref arena) => result.quota_papers[&1].ballots(arena),
+        Ballots::Trie(_) => panic!("ballots held in a trie"),
+    };
+    assert!(papers.iter().all(|(_, value)| *value >= Frac::zero()));
+    assert_eq!(countback.quota_value, frac!(69u32 * 8, 9u32));
+
+    // Candidates 3 and 4 have no votes, and the tie between them is recorded.
+    let votes = |papers: u32| frac!(8 * papers, 9u32);
+    assert_eq!(
+        countback.rounds[0].votes,
+        vec![
+            (2, votes(18)),
+            (3, Frac::zero()),
+            (4, Frac::zero()),
+            (5, votes(15)),
+            (6, votes(16))
+        ]
+    );
+    assert_eq!(countback.rounds[0].excluded, Some(4));
+    assert_eq!(countback.ties.len(), 1);
+    let tie = &countback.ties[0];
+    assert_eq!(tie.count, 1);
+    assert_eq!(tie.kind, TieKind::Exclusion);
+    assert_eq!(tie.candidates, vec![3, 4]);
+    assert_eq!(tie.chosen, 4);
+    assert_eq!(tie.settled_by, TieSettlement::TieBreaker);
+    assert_eq!(countback.elected.id, 2);
+}
+
+#[test]
+fn quota_formulas() {
+    // With 30 votes and 2 vacancies, the division is exact, which is where the statutory Droop
+    // quota differs from rounding up.
+    let quota = |formula: QuotaFormula| formula.value(30, 2);
+    assert_eq!(quota(QuotaFormula::Droop), frac!(10u32));
+    assert_eq!(quota(QuotaFormula::StatutoryDroop), frac!(11u32));
+    assert_eq!(quota(QuotaFormula::ExactDroop), frac!(10u32));
+    assert_eq!(quota(QuotaFormula::Hare), frac!(15u32));
+    assert_eq!(quota(QuotaFormula::Imperiali), frac!(15u32, 2u32));
+    assert_eq!("exact-droop".parse(), Ok(QuotaFormula::ExactDroop));
+
+    // 16 papers for candidate 0 and 14 for candidate 1 (preferring candidate 2). Under the
+    // Imperiali quota of 7 1/2, candidates 0 and 1 both reach it on first preferences, and fill
+    // the two vacancies.
+    let candidates = candidate_map(3);
+    let mut all_ballots = ballots(16, &[0]);
+    all_ballots.extend(ballots(14, &[1, 2]));
+    let count = |formula| {
+        let options = CountOptions {
+            quota: Some(formula),
+            ..CountOptions::default()
+        };
+        count_ballots(&candidates, all_ballots.clone(), 2, &options)
+    };
+
+    let imperiali = count(QuotaFormula::Imperiali);
+    assert_eq!(imperiali.quota_formula, QuotaFormula::Imperiali);
+    assert_eq!(imperiali.quota, frac!(15u32, 2u32));
+    assert_eq!(elected_ids(&imperiali.senators), vec![0, 1]);
+
+    // Under the Hare quota of 15, candidate 1's papers can't elect them, and candidate 0's
+    // surplus of 1 is transferred at 1/16 a paper, so candidate 1 fills the last vacancy on the
+    // last two candidates rule.
+    let hare = count(QuotaFormula::Hare);
+    assert_eq!(hare.quota, frac!(15u32));
+    assert_eq!(elected_ids(&hare.senators), vec![0, 1]);
+    assert!(hare.last_seat_without_quota);
+
+    assert_eq!(
+        hare.transcript.counts[1].action,
+        CountAction::Surplus {
+            from: 0,
+            transfer_value: frac!(1u32, 16u32)
+        }
+    );
+
+    // An exact Droop quota of 31/3 leaves a fractional surplus.
+    let exact = QuotaFormula::ExactDroop.value(31, 2);
+    let surplus: Surplus<Paper> = Surplus::new(0, Int::from(16), exact, TransferMap::new());
+    assert_eq!(surplus.amount(), frac!(17u32, 3u32));
+}
+
+#[test]
+fn instant_runoff() {
+    let candidates = candidate_map(3);
+    let mut all_ballots = ballots(8, &[0]);
+    all_ballots.extend(ballots(7, &[2]));
+    all_ballots.extend(ballots(4, &[1]));
+    all_ballots.extend(ballots(2, &[1, 2]));
+    let options = CountOptions {
+        irv: true,
+        ..CountOptions::default()
+    };
+    let count = |num_positions| {
+        decide_election(
+            &candidates,
+            &[],
+            all_ballots.clone().into_iter().map(Ok),
+            num_positions,
+            &options,
+        )
+    };
+
+    let result = count(1).unwrap();
+    assert_eq!(elected_ids(&result.senators), vec![2]);
+    assert_eq!(result.quota_formula, QuotaFormula::Majority);
+
+    // Candidate 1 is excluded, and 4 of their papers exhaust, so the majority falls from 11 of 21
+    // votes to 9 of 17.
+    let report = result.irv.unwrap();
+    assert_eq!(report.rounds.len(), 2);
+    assert_eq!(report.rounds[0].majority, 11);
+    assert_eq!(report.rounds[0].excluded, Some(1));
+    assert_eq!(report.rounds[1].votes, vec![(0, 8), (2, 9)]);
+    assert_eq!(report.rounds[1].exhausted, 4);
+    assert_eq!(report.rounds[1].majority, 9);
+    assert_eq!(report.rounds[1].elected, Some(2));
+    assert_eq!(report.candidate_votes(1), vec![Some(6), None]);
+    assert!(!result.last_seat_without_quota);
+
+    assert!(count(2).is_err());
+
+    // Candidates 1 and 2 tie for exclusion, which is recorded with the ties of other counts.
+    let mut tied_ballots = ballots(4, &[0]);
+    tied_ballots.extend(ballots(2, &[1, 0]));
+    tied_ballots.extend(ballots(2, &[2, 0]));
+    let scripted = CountOptions {
+        tie_break: TieBreakMethod::Scripted(vec![1]),
+        ..options.clone()
+    };
+    let result = count_ballots(&candidates, tied_ballots, 1, &scripted);
+    assert_eq!(elected_ids(&result.senators), vec![0]);
+    assert_eq!(result.ties.len(), 1);
+    assert_eq!(result.ties[0].count, 1);
+    assert_eq!(result.ties[0].kind, TieKind::Exclusion);
+    assert_eq!(result.ties[0].candidates, vec![1, 2]);
+    assert_eq!(result.ties[0].chosen, 2);
+    assert_eq!(result.irv.unwrap().rounds[0].excluded, Some(2));
+
+    let both = CountOptions {
+        meek: Some(MeekOptions::default()),
+        ..options.clone()
+    };
+    assert!(decide_election(&candidates, &[], all_ballots.into_iter().map(Ok), 1, &both,).is_err());
+}
+
+#[test]
+fn identical_ballots_merged() {
+    // Merging the 22 papers into 4 weighted ballots doesn't change the count, and the 3 papers
+    // exhausted by candidate 0's surplus are still counted individually.
+    let (candidates, all_ballots) = surplus_election();
+    let mut weighted = vec![Ballot::multi(2, vec![0, 1]), Ballot::multi(3, vec![0])];
+    weighted.extend(ballots(8, &[0, 1]));
+    weighted.extend(ballots(4, &[2]));
+    weighted.push(Ballot::multi(5, vec![3]));
+
+    let count =
+        |ballots: Vec<Ballot>| count_ballots(&candidates, ballots, 2, &CountOptions::default());
+    let unmerged = count(all_ballots);
+    let merged = count(weighted);
+
+    assert_eq!(merged.stats.num_valid_votes(), 22);
+    assert_eq!(
+        elected_ids(&merged.senators),
+        elected_ids(&unmerged.senators)
+    );
+    assert_eq!(
+        dop::rows(&merged, &candidates),
+        dop::rows(&unmerged, &candidates)
+    );
+    assert_eq!(merged.transcript.counts[1].exhausted.papers, 3);
+    assert_eq!(
+        exhausted_votes::from_transcript(&merged.transcript),
+        exhausted_votes::from_transcript(&unmerged.transcript)
+    );
+    assert_eq!(
+        exhausted_votes::from_transcript(&merged.transcript)[&1],
+        (3, frac!(15u32, 13u32))
+    );
+    assert_eq!(
+        merged.transcript.counts[1].exhausted.votes_change,
+        Int::from(1)
+    );
+}
+
+#[test]
+fn ballot_trie() {
+    // Candidate 0's surplus and candidate 4's exclusion pass over candidates already excluded or
+    // elected, and some of the papers exhaust along the way.
+    let candidates = candidate_map(6);
+    let mut all_ballots = ballots(12, &[0, 4, 1]);
+    all_ballots.extend(ballots(6, &[0, 5, 2, 3]));
+    all_ballots.extend(ballots(3, &[0]));
+    all_ballots.extend(ballots(7, &[1, 0, 2]));
+    all_ballots.extend(ballots(6, &[2, 5]));
+    all_ballots.extend(ballots(5, &[3, 1]));
+    all_ballots.extend(ballots(2, &[4, 5, 0, 3]));
+    all_ballots.extend(ballots(3, &[5, 4, 2]));
+
+    let rules: Vec<Rc<dyn CountingRules>> = vec![
+        Rc::new(Senate2016::default()),
+        Rc::new(Senate2016 {
+            bulk_exclusion: true,
+        }),
+        Rc::new(WeightedInclusiveGregory {
+            decimal_places: Some(4),
+            bulk_exclusion: false,
+        }),
+    ];
+    for rules in rules {
+        for disqualified in &[vec![], vec![4]] {
+            let count = |ballot_trie| {
+                let options = CountOptions {
+                    rules: rules.clone(),
+                    ballot_trie,
+                    ..CountOptions::default()
+                };
+                decide_election(
+                    &candidates,
+                    disqualified,
+                    all_ballots.clone().into_iter().map(Ok),
+                    3,
+                    &options,
+                )
+                .unwrap()
+            };
+            let (papers, trie) = (count(false), count(true));
+
+            assert_eq!(elected_ids(&trie.senators), elected_ids(&papers.senators));
+            assert_eq!(
+                dop::rows(&trie, &candidates),
+                dop::rows(&papers, &candidates)
+            );
+            assert_eq!(
+                exhausted_votes::from_transcript(&trie.transcript),
+                exhausted_votes::from_transcript(&papers.transcript)
+            );
+            let original = |result: &Senate| {
+                result
+                    .original_senators
+                    .as_ref()
+                    .map(|senators| senators.iter().map(|c| c.id).collect::<Vec<_>>())
+            };
+            assert_eq!(original(&trie), original(&papers));
+        }
+    }
+
+    // Hare-Clark looks at the papers of the last parcel one by one.
+    let options = CountOptions {
+        rules: Rc::new(HareClark),
+        ballot_trie: true,
+        ..CountOptions::default()
+    };
+    let result = decide_election(
+        &candidates,
+        &[],
+        all_ballots.into_iter().map(Ok),
+        3,
+        &options,
+    );
+    assert!(result.is_err());
+}